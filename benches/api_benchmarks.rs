@@ -1,5 +1,10 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
-use hft_service::{app_router, store::Store, SharedState};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use hft_service::{
+    app_router, binary, config::RetentionConfig, segment_tree::SegmentTree, store::Store,
+    SharedState,
+};
 use tokio::runtime::Runtime;
 
 use axum::{
@@ -43,24 +48,11 @@ fn bench_get_stats(c: &mut Criterion) {
     let state = SharedState::new(Store::new());
     let app = app_router(state.clone());
 
-    // Setup block to pre-load data
-    rt.block_on(async {
-        let mut data_guard = state
-            .symbols
-            .entry("BENCH-SYM".to_string())
-            .or_insert_with(|| hft_service::store::SymbolData {
-                values: Vec::new(),
-                tree: hft_service::segment_tree::SegmentTree::new(1_000_000),
-            });
-
-        let hft_service::store::SymbolData { values, tree } = &mut *data_guard;
-
-        for i in 0..1_000_000 {
-            let value = 150.0 + (i % 10) as f64;
-            values.push(value);
-            tree.update(i, value, values);
-        }
-    });
+    // Setup block to pre-load data directly into the store, bypassing HTTP.
+    let values: Vec<f64> = (0..1_000_000).map(|i| 150.0 + (i % 10) as f64).collect();
+    for chunk in values.chunks(10_000) {
+        state.add_batch("BENCH-SYM", chunk).unwrap();
+    }
 
     c.bench_function("GET /stats (1M points, exponent=6)", |b| {
         b.to_async(&rt).iter(|| async {
@@ -109,21 +101,17 @@ fn bench_get_stats_complexity(c: &mut Criterion) {
             }
         });
 
-        group.bench_with_input(
-            criterion::BenchmarkId::from_parameter(n_points),
-            n_points,
-            |b, _| {
-                b.to_async(&rt).iter(|| async {
-                    let request = Request::builder()
-                        .uri("/stats/?symbol=COMPLEXITY&exponent=2") // Always query for a fixed window (last 100)
-                        .body(Body::empty())
-                        .unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(n_points), n_points, |b, _| {
+            b.to_async(&rt).iter(|| async {
+                let request = Request::builder()
+                    .uri("/stats/?symbol=COMPLEXITY&exponent=2") // Always query for a fixed window (last 100)
+                    .body(Body::empty())
+                    .unwrap();
 
-                    let response = black_box(app.clone().oneshot(request).await.unwrap());
-                    assert_eq!(response.status(), StatusCode::OK);
-                });
-            },
-        );
+                let response = black_box(app.clone().oneshot(request).await.unwrap());
+                assert_eq!(response.status(), StatusCode::OK);
+            });
+        });
     }
     group.finish();
 }
@@ -166,7 +154,7 @@ fn bench_get_stats_window_size(c: &mut Criterion) {
         group.throughput(Throughput::Elements(window_size));
 
         group.bench_with_input(
-            criterion::BenchmarkId::from_parameter(window_size),
+            BenchmarkId::from_parameter(window_size),
             exponent,
             |b, &exp| {
                 b.to_async(&rt).iter(|| async {
@@ -184,11 +172,193 @@ fn bench_get_stats_window_size(c: &mut Criterion) {
     group.finish();
 }
 
+/// Measures the stall when a full tree has to grow, comparing the old per-point
+/// rebuild (every value walks to the root, O(N log N)) with the bottom-up O(N) build.
+fn bench_segment_tree_resize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("SegmentTree resize stall");
+    group.sample_size(10);
+
+    for n_points in [100_000usize, 1_000_000, 10_000_000] {
+        group.throughput(Throughput::Elements(n_points as u64));
+        let mut values: Vec<f64> = (0..n_points).map(|i| 150.0 + (i % 10) as f64).collect();
+
+        // Before: rebuild a tree of double the capacity by updating every point.
+        group.bench_with_input(
+            BenchmarkId::new("per-point rebuild", n_points),
+            &values,
+            |b, values| {
+                b.iter(|| {
+                    let mut tree = SegmentTree::new(2 * values.len());
+                    for (i, &v) in values.iter().enumerate() {
+                        tree.update(i, v, values);
+                    }
+                    black_box(tree)
+                });
+            },
+        );
+
+        // After: append one point to a full tree, which triggers `resize`.
+        values.push(150.0);
+        group.bench_with_input(
+            BenchmarkId::new("bottom-up resize", n_points),
+            &values,
+            |b, values| {
+                b.iter_batched(
+                    || SegmentTree::from_values(&values[..n_points]),
+                    |mut tree| {
+                        tree.batch_update(n_points, &values[n_points..], values);
+                        black_box(tree)
+                    },
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Measures exact quantile queries against windows of increasing size, which
+/// should grow polylogarithmically rather than linearly.
+fn bench_get_quantiles_window_size(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("GET /quantiles complexity vs window_size");
+
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+    let values: Vec<f64> = (0..1_000_000)
+        .map(|i| 150.0 + ((i * 7919) % 1000) as f64 / 100.0)
+        .collect();
+    for chunk in values.chunks(10_000) {
+        state.add_batch("BENCH-SYM", chunk).unwrap();
+    }
+
+    for window in [1_000usize, 10_000, 100_000, 1_000_000] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(window),
+            &window,
+            |b, &window| {
+                let uri = format!(
+                    "/quantiles/?symbol=BENCH-SYM&window={}&q=0.5,0.95,0.99",
+                    window
+                );
+                b.to_async(&rt).iter(|| async {
+                    let request = Request::builder()
+                        .uri(uri.as_str())
+                        .body(Body::empty())
+                        .unwrap();
+
+                    let response = black_box(app.clone().oneshot(request).await.unwrap());
+                    assert_eq!(response.status(), StatusCode::OK);
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Compares one multi-window request against the eight single-window requests
+/// a dashboard would otherwise make (exponent 1 to 8) for the same symbol.
+fn bench_get_stats_multi_window(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("GET /stats eight windows");
+
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+    let values: Vec<f64> = (0..1_000_000).map(|i| 150.0 + (i % 10) as f64).collect();
+    for chunk in values.chunks(10_000) {
+        state.add_batch("BENCH-SYM", chunk).unwrap();
+    }
+
+    group.bench_function("eight separate requests", |b| {
+        b.to_async(&rt).iter(|| async {
+            for exponent in 1..=8 {
+                let request = Request::builder()
+                    .uri(format!("/stats/?symbol=BENCH-SYM&exponent={}", exponent))
+                    .body(Body::empty())
+                    .unwrap();
+
+                let response = black_box(app.clone().oneshot(request).await.unwrap());
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        });
+    });
+
+    let windows: Vec<String> = (1..=8)
+        .map(|exponent| 10u64.pow(exponent).to_string())
+        .collect();
+    let uri = format!("/stats/?symbol=BENCH-SYM&windows={}", windows.join(","));
+    group.bench_function("one multi-window request", |b| {
+        b.to_async(&rt).iter(|| async {
+            let request = Request::builder()
+                .uri(uri.as_str())
+                .body(Body::empty())
+                .unwrap();
+
+            let response = black_box(app.clone().oneshot(request).await.unwrap());
+            assert_eq!(response.status(), StatusCode::OK);
+        });
+    });
+    group.finish();
+}
+
+/// Compares decoding and adding the same 10k-point batch sent as JSON and in
+/// the binary format. Prices carry full precision, as market data does, since
+/// parsing long decimals is what makes JSON expensive.
+fn bench_add_batch_json_vs_binary(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("POST /add_batch JSON vs binary (10k points)");
+    group.throughput(Throughput::Elements(10_000));
+
+    let values: Vec<f64> = (0..10_000)
+        .map(|i| 150.0 + (i as f64 * 0.0137).sin())
+        .collect();
+    let bodies = [
+        (
+            "json",
+            "application/json",
+            serde_json::to_vec(&json!({ "symbol": "BENCH-SYM", "values": values })).unwrap(),
+        ),
+        (
+            "binary",
+            binary::BATCH_CONTENT_TYPE,
+            binary::encode_batch("BENCH-SYM", &values, None, Default::default()),
+        ),
+    ];
+
+    for (name, content_type, body) in bodies {
+        // Bounded retention keeps the store at a steady size, so occasional
+        // storage growth doesn't drown out the difference in decoding.
+        let store = Store::new().with_retention(RetentionConfig {
+            max_points: 100_000,
+            ..Default::default()
+        });
+        let app = app_router(SharedState::new(store));
+        group.bench_function(name, |b| {
+            b.to_async(&rt).iter(|| async {
+                let request = Request::builder()
+                    .uri("/add_batch/")
+                    .method("POST")
+                    .header("content-type", content_type)
+                    .body(Body::from(body.clone()))
+                    .unwrap();
+
+                let response = black_box(app.clone().oneshot(request).await.unwrap());
+                assert_eq!(response.status(), StatusCode::OK);
+            });
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_add_batch,
     bench_get_stats,
     bench_get_stats_complexity,
-    bench_get_stats_window_size
+    bench_get_stats_window_size,
+    bench_segment_tree_resize,
+    bench_get_quantiles_window_size,
+    bench_get_stats_multi_window,
+    bench_add_batch_json_vs_binary
 );
 criterion_main!(benches);
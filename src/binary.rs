@@ -0,0 +1,201 @@
+use crate::{validation::InvalidValuePolicy, AppError};
+
+/// The content type that selects the binary format for `/add_batch/`.
+pub const BATCH_CONTENT_TYPE: &str = "application/x-hft-batch";
+
+/// Set if a timestamp follows for every value.
+const FLAG_TIMESTAMPS: u8 = 1 << 0;
+/// Set if invalid values are dropped instead of rejecting the batch.
+const FLAG_DROP_INVALID: u8 = 1 << 1;
+
+// Batch layout (all integers little-endian):
+//
+//   symbol_len: u32   symbol: [u8; symbol_len]   (UTF-8)
+//   flags: u8         (FLAG_TIMESTAMPS | FLAG_DROP_INVALID, other bits zero)
+//   count: u32        values: [f64; count]
+//   timestamps: [u64; count]   (only with FLAG_TIMESTAMPS)
+//
+// The body must end right after the last value or timestamp.
+
+/// A batch decoded from the binary format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryBatch {
+    pub symbol: String,
+    pub values: Vec<f64>,
+    /// Milliseconds since the Unix epoch, one per value, if the batch has them.
+    pub timestamps: Option<Vec<u64>>,
+    pub on_invalid: InvalidValuePolicy,
+}
+
+/// Encodes a batch in the binary format.
+///
+/// # Panics
+///
+/// Panics if `timestamps` is given with a different length than `values`, or
+/// if the symbol or the batch is too long for its `u32` length prefix.
+pub fn encode_batch(
+    symbol: &str,
+    values: &[f64],
+    timestamps: Option<&[u64]>,
+    on_invalid: InvalidValuePolicy,
+) -> Vec<u8> {
+    if let Some(timestamps) = timestamps {
+        assert_eq!(timestamps.len(), values.len(), "one timestamp per value");
+    }
+    let symbol_len = u32::try_from(symbol.len()).expect("symbol too long");
+    let count = u32::try_from(values.len()).expect("batch too long");
+    let mut flags = 0;
+    if timestamps.is_some() {
+        flags |= FLAG_TIMESTAMPS;
+    }
+    if on_invalid == InvalidValuePolicy::Drop {
+        flags |= FLAG_DROP_INVALID;
+    }
+
+    let per_value = if timestamps.is_some() { 16 } else { 8 };
+    let mut bytes = Vec::with_capacity(9 + symbol.len() + values.len() * per_value);
+    bytes.extend_from_slice(&symbol_len.to_le_bytes());
+    bytes.extend_from_slice(symbol.as_bytes());
+    bytes.push(flags);
+    bytes.extend_from_slice(&count.to_le_bytes());
+    for value in values {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for timestamp in timestamps.unwrap_or_default() {
+        bytes.extend_from_slice(&timestamp.to_le_bytes());
+    }
+    bytes
+}
+
+/// Decodes a batch in the binary format. Only the layout is checked here; the
+/// values themselves are validated like those of a JSON batch.
+pub fn decode_batch(bytes: &[u8]) -> Result<BinaryBatch, AppError> {
+    let mut reader = Reader { bytes };
+
+    let symbol_len = reader.read_u32()? as usize;
+    let symbol = std::str::from_utf8(reader.take(symbol_len)?)
+        .map_err(|_| invalid("symbol is not valid UTF-8"))?
+        .to_string();
+    let flags = reader.take(1)?[0];
+    if flags & !(FLAG_TIMESTAMPS | FLAG_DROP_INVALID) != 0 {
+        return Err(invalid(&format!("unknown flags {:#04x}", flags)));
+    }
+    let count = reader.read_u32()? as usize;
+
+    // Check the length up front, so a bogus count can't cause a huge allocation.
+    let per_value = if flags & FLAG_TIMESTAMPS != 0 { 16 } else { 8 };
+    if count.checked_mul(per_value) != Some(reader.bytes.len()) {
+        return Err(invalid(&format!(
+            "expected {} bytes of data for {} values, found {}",
+            count.saturating_mul(per_value),
+            count,
+            reader.bytes.len()
+        )));
+    }
+    let (values, rest) = reader.bytes.split_at(count * 8);
+    let values = values
+        .chunks_exact(8)
+        .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    let timestamps = (flags & FLAG_TIMESTAMPS != 0).then(|| {
+        rest.chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    });
+
+    Ok(BinaryBatch {
+        symbol,
+        values,
+        timestamps,
+        on_invalid: if flags & FLAG_DROP_INVALID != 0 {
+            InvalidValuePolicy::Drop
+        } else {
+            InvalidValuePolicy::Reject
+        },
+    })
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::BadRequest(format!("Invalid binary batch: {}", reason))
+}
+
+/// Reads a binary batch front to back.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], AppError> {
+        if self.bytes.len() < len {
+            return Err(invalid("unexpected end of data"));
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, AppError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let values = [1.5, f64::NAN, 0.0];
+        let timestamps = [10, 20, 30];
+
+        let plain = decode_batch(&encode_batch(
+            "ABC",
+            &values[..1],
+            None,
+            InvalidValuePolicy::Reject,
+        ))
+        .unwrap();
+        assert_eq!(plain.symbol, "ABC");
+        assert_eq!(plain.values, vec![1.5]);
+        assert_eq!(plain.timestamps, None);
+        assert_eq!(plain.on_invalid, InvalidValuePolicy::Reject);
+
+        let timed = decode_batch(&encode_batch(
+            "ÅBC",
+            &values,
+            Some(&timestamps),
+            InvalidValuePolicy::Drop,
+        ))
+        .unwrap();
+        assert_eq!(timed.symbol, "ÅBC");
+        assert!(timed.values[1].is_nan());
+        assert_eq!(timed.timestamps, Some(timestamps.to_vec()));
+        assert_eq!(timed.on_invalid, InvalidValuePolicy::Drop);
+    }
+
+    #[test]
+    fn test_rejects_malformed_batches() {
+        let valid = encode_batch("ABC", &[1.0, 2.0], Some(&[1, 2]), Default::default());
+
+        // Truncated anywhere, or with trailing bytes.
+        for len in 0..valid.len() {
+            assert!(decode_batch(&valid[..len]).is_err(), "length {}", len);
+        }
+        let mut trailing = valid.clone();
+        trailing.push(0);
+        assert!(decode_batch(&trailing).is_err());
+
+        // Unknown flags, and a count far larger than the data.
+        let mut flags = valid.clone();
+        flags[7] |= 0x80;
+        assert!(decode_batch(&flags).is_err());
+        let mut count = valid.clone();
+        count[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode_batch(&count).is_err());
+
+        // A symbol that is not UTF-8.
+        let mut symbol = valid;
+        symbol[4] = 0xff;
+        assert!(decode_batch(&symbol).is_err());
+    }
+}
@@ -1,9 +1,13 @@
+use crate::wal::FsyncPolicy;
 use figment::{
     providers::{Env, Format, Toml},
     Figment,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ServerConfig {
@@ -16,19 +20,185 @@ pub struct LogConfig {
     pub level: String,
 }
 
+/// Controls how many points each symbol keeps. A limit of `0` means unbounded;
+/// otherwise only the most recent points are kept and older ones are overwritten.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RetentionConfig {
+    /// The default limit for every symbol.
+    #[serde(default)]
+    pub max_points: usize,
+    /// Per-symbol limits that override `max_points`.
+    #[serde(default)]
+    pub symbols: HashMap<String, usize>,
+}
+
+impl RetentionConfig {
+    /// Returns the retention limit for `symbol`, or `None` if it is unbounded.
+    pub fn limit_for(&self, symbol: &str) -> Option<usize> {
+        let limit = self.symbols.get(symbol).copied().unwrap_or(self.max_points);
+        (limit > 0).then_some(limit)
+    }
+}
+
+/// Controls periodic snapshots of the store to disk.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SnapshotConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "SnapshotConfig::default_path")]
+    pub path: PathBuf,
+    /// Seconds between periodic snapshots (0 = only on shutdown and on demand).
+    #[serde(default)]
+    pub interval_secs: u64,
+}
+
+impl SnapshotConfig {
+    fn default_path() -> PathBuf {
+        PathBuf::from("data/store.snapshot")
+    }
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: Self::default_path(),
+            interval_secs: 0,
+        }
+    }
+}
+
+/// Service limits. Any field left out of `Config.toml` keeps its default.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// The maximum size of a batch we can accept in a single request.
+    pub max_batch_size: usize,
+    /// The maximum number of unique symbols we can track.
+    pub max_symbols: usize,
+    /// The initial capacity of a new symbol's storage. It grows geometrically
+    /// from there, so sparse symbols stay small.
+    pub starting_capacity: usize,
+    /// The largest `capacity_hint` an ingestion request may ask for. Every slot
+    /// costs about 128 bytes (value, timestamp and two tree nodes), so a single
+    /// request may allocate about `128 * max_capacity_hint` bytes per symbol.
+    pub max_capacity_hint: usize,
+    /// The smallest `exponent` a stats query may use.
+    pub min_exponent: u32,
+    /// The largest `exponent` a stats query may use.
+    pub max_exponent: u32,
+    /// The largest explicit `window` a stats query may use.
+    pub max_window: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 10_000,
+            max_symbols: 10,
+            starting_capacity: 64,
+            max_capacity_hint: 100_000,
+            min_exponent: 1,
+            max_exponent: 8,
+            max_window: 100_000_000,
+        }
+    }
+}
+
+/// When the write-ahead log is flushed to stable storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncMode {
+    EveryBatch,
+    #[default]
+    Interval,
+    Never,
+}
+
+/// Controls the write-ahead log that makes batches durable between snapshots.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WalConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "WalConfig::default_dir")]
+    pub dir: PathBuf,
+    #[serde(default)]
+    pub fsync: FsyncMode,
+    /// Milliseconds between syncs when `fsync` is `interval`.
+    #[serde(default = "WalConfig::default_fsync_interval_ms")]
+    pub fsync_interval_ms: u64,
+}
+
+impl WalConfig {
+    fn default_dir() -> PathBuf {
+        PathBuf::from("data/wal")
+    }
+
+    fn default_fsync_interval_ms() -> u64 {
+        100
+    }
+
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        match self.fsync {
+            FsyncMode::EveryBatch => FsyncPolicy::EveryBatch,
+            FsyncMode::Interval => {
+                FsyncPolicy::Interval(Duration::from_millis(self.fsync_interval_ms))
+            }
+            FsyncMode::Never => FsyncPolicy::Never,
+        }
+    }
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: Self::default_dir(),
+            fsync: FsyncMode::default(),
+            fsync_interval_ms: Self::default_fsync_interval_ms(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub log: LogConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+    #[serde(default)]
+    pub wal: WalConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
 }
 
 impl Config {
     #[allow(dead_code)]
     pub fn new() -> Result<Self, Box<dyn Error>> {
-        Figment::new()
-            .merge(Toml::file("Config.toml"))
-            .merge(Env::prefixed("APP_"))
-            .extract()
-            .map_err(|e| e.into())
+        Self::load("Config.toml", "APP_")
+    }
+
+    /// Loads `toml_path`, then applies environment variables starting with
+    /// `env_prefix`. Nested keys are separated by `__`, so with the `APP_` prefix
+    /// `APP_LIMITS__MAX_SYMBOLS=250` sets `limits.max_symbols`.
+    pub fn load(toml_path: &str, env_prefix: &str) -> Result<Self, Box<dyn Error>> {
+        let config: Self = Figment::new()
+            .merge(Toml::file(toml_path))
+            .merge(Env::prefixed(env_prefix).split("__"))
+            .extract()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Rejects settings that are valid on their own but not together.
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        // Only snapshots delete log segments, so without them the log would grow
+        // forever and be replayed in full on every start.
+        if self.wal.enabled && !self.snapshot.enabled {
+            return Err("[wal] enabled requires [snapshot] enabled".into());
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,138 @@
+use crate::{resolve_ddof, resolve_window_size, ws::Subscription, AppError, SharedState};
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::stream::{self, Stream};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, instrument, warn};
+
+/// The most symbols a single `/events` stream may follow.
+const MAX_EVENT_SYMBOLS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct EventsRequest {
+    /// A comma-separated list of symbols, such as `ABC-USD,XYZ-USD`.
+    symbols: String,
+    exponent: Option<u32>,
+    window: Option<usize>,
+    ddof: Option<u32>,
+}
+
+/// The state of one `/events` stream between events.
+struct EventStream {
+    state: SharedState,
+    updates: broadcast::Receiver<Arc<str>>,
+    closed: Pin<Box<dyn Future<Output = ()> + Send>>,
+    subscriptions: Vec<Subscription>,
+    /// Events that are ready to be sent, oldest first.
+    queued: VecDeque<Event>,
+    /// Whether every followed symbol is due, as on the first event or after
+    /// missing notifications.
+    refresh_all: bool,
+}
+
+impl EventStream {
+    /// Queues the current statistics of every followed symbol that `changed`.
+    fn queue(&mut self, changed: impl Fn(&str) -> bool) -> Result<(), axum::Error> {
+        for subscription in &self.subscriptions {
+            if changed(&subscription.symbol) {
+                let event = Event::default()
+                    .event("stats")
+                    .json_data(subscription.frame(&self.state))?;
+                self.queued.push_back(event);
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the next event, or `None` once the server starts shutting down.
+    async fn next(&mut self) -> Option<Result<Event, axum::Error>> {
+        loop {
+            if let Some(event) = self.queued.pop_front() {
+                return Some(Ok(event));
+            }
+            if std::mem::take(&mut self.refresh_all) {
+                if let Err(e) = self.queue(|_| true) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+            let queued = tokio::select! {
+                _ = &mut self.closed => return None,
+                update = self.updates.recv() => match update {
+                    Ok(symbol) => self.queue(|s| s == &*symbol),
+                    Err(RecvError::Lagged(missed)) => {
+                        // Some notifications were discarded, so any symbol may have changed.
+                        warn!(missed, "Event stream fell behind the store");
+                        self.refresh_all = true;
+                        Ok(())
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+            if let Err(e) = queued {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Streams the statistics of each symbol over the same trailing window as
+/// Server-Sent Events: once for every symbol straight away, and then again
+/// whenever it changes. The stream ends when the server shuts down.
+#[instrument(name = "events_request", skip(state), fields(symbols = %params.symbols, exponent = ?params.exponent, window = ?params.window, ddof = ?params.ddof))]
+pub(crate) async fn events_handler(
+    State(state): State<SharedState>,
+    Query(params): Query<EventsRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    let window = resolve_window_size(state.limits(), params.exponent, params.window)?;
+    let ddof = resolve_ddof(params.ddof)?;
+
+    let mut symbols: Vec<&str> = Vec::new();
+    for symbol in params.symbols.split(',').map(str::trim) {
+        if symbol.is_empty() {
+            return Err(AppError::BadRequest(
+                "symbols must be a comma-separated list of symbol names".to_string(),
+            ));
+        }
+        if !symbols.contains(&symbol) {
+            symbols.push(symbol);
+        }
+    }
+    if symbols.len() > MAX_EVENT_SYMBOLS {
+        return Err(AppError::BadRequest(format!(
+            "At most {} symbols may be followed at once",
+            MAX_EVENT_SYMBOLS
+        )));
+    }
+
+    // Subscribe before taking the first snapshots, so no later change is missed.
+    let events = EventStream {
+        updates: state.subscribe(),
+        closed: Box::pin(state.streams_closed()),
+        subscriptions: symbols
+            .into_iter()
+            .map(|symbol| Subscription {
+                symbol: symbol.to_string(),
+                window,
+                ddof,
+            })
+            .collect(),
+        queued: VecDeque::new(),
+        refresh_all: true,
+        state,
+    };
+
+    info!("Event stream opened");
+    let stream = stream::unfold(events, |mut events| async move {
+        let event = events.next().await?;
+        Some((event, events))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,184 @@
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        request::Parts,
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::convert::Infallible;
+use tracing::error;
+
+/// How a response body is encoded, chosen from the request's `Accept` header.
+/// Every format uses the same field names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ResponseFormat {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl ResponseFormat {
+    /// Picks the format the client prefers, by `q` value and then by order.
+    /// Anything other than MessagePack or CBOR, including a missing header or
+    /// one naming no supported type, gets JSON.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let mut best: Option<(f32, Self)> = None;
+        for value in headers.get_all(ACCEPT) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for range in value.split(',') {
+                let mut params = range.split(';');
+                let Some(format) = params.next().and_then(|mime| Self::from_mime(mime.trim()))
+                else {
+                    continue;
+                };
+                let q = params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                if q > 0.0 && best.is_none_or(|(best_q, _)| q > best_q) {
+                    best = Some((q, format));
+                }
+            }
+        }
+        best.map(|(_, format)| format).unwrap_or_default()
+    }
+
+    fn from_mime(mime: &str) -> Option<Self> {
+        match mime.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Self::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MessagePack)
+            }
+            "application/cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+        }
+    }
+
+    /// Encodes `value` in this format, with its status, as a response.
+    pub(crate) fn respond(self, status: StatusCode, value: &impl Serialize) -> Response {
+        let body = match self {
+            // Keep axum's own JSON encoding, and its error handling, for JSON.
+            Self::Json => return (status, Json(value)).into_response(),
+            // Named, so that maps carry the same field names as JSON.
+            Self::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Self::Cbor => {
+                let mut body = Vec::new();
+                ciborium::into_writer(value, &mut body)
+                    .map(|()| body)
+                    .map_err(|e| e.to_string())
+            }
+        };
+        match body {
+            Ok(body) => (
+                status,
+                [(CONTENT_TYPE, HeaderValue::from_static(self.content_type()))],
+                body,
+            )
+                .into_response(),
+            Err(e) => {
+                error!(error = %e, format = ?self, "Failed to encode response");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for ResponseFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+/// A `200 OK` response body, encoded in the format the client asked for.
+pub(crate) struct Encoded<T>(pub ResponseFormat, pub T);
+
+impl<T: Serialize> IntoResponse for Encoded<T> {
+    fn into_response(self) -> Response {
+        self.0.respond(StatusCode::OK, &self.1)
+    }
+}
+
+/// The body of an error response, attached to it by `AppError` so that it can
+/// be re-encoded once the requested format is known.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ErrorBody {
+    pub(crate) error: String,
+}
+
+/// Re-encodes error bodies in the format the request asked for. `AppError`
+/// can't see the request, so it always renders JSON.
+pub(crate) async fn encode_errors(request: Request, next: Next) -> Response {
+    let format = ResponseFormat::from_headers(request.headers());
+    let mut response = next.run(request).await;
+    if format == ResponseFormat::Json {
+        return response;
+    }
+    match response.extensions_mut().remove::<ErrorBody>() {
+        Some(body) => format.respond(response.status(), &body),
+        None => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(accept: &[&str]) -> ResponseFormat {
+        let mut headers = HeaderMap::new();
+        for value in accept {
+            headers.append(ACCEPT, HeaderValue::from_str(value).unwrap());
+        }
+        ResponseFormat::from_headers(&headers)
+    }
+
+    #[test]
+    fn test_negotiates_preferred_format() {
+        assert_eq!(negotiate(&[]), ResponseFormat::Json);
+        assert_eq!(negotiate(&["*/*"]), ResponseFormat::Json);
+        assert_eq!(negotiate(&["text/html"]), ResponseFormat::Json);
+        assert_eq!(
+            negotiate(&["application/msgpack"]),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            negotiate(&["Application/X-MsgPack"]),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(negotiate(&["application/cbor"]), ResponseFormat::Cbor);
+
+        // By q value, then by order; q=0 means "not acceptable".
+        assert_eq!(
+            negotiate(&["application/json;q=0.5, application/cbor"]),
+            ResponseFormat::Cbor
+        );
+        assert_eq!(
+            negotiate(&["application/cbor; q=0.2", "application/msgpack; q=0.9"]),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            negotiate(&["application/msgpack, application/cbor"]),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(negotiate(&["application/cbor;q=0"]), ResponseFormat::Json);
+    }
+}
@@ -1,6 +1,8 @@
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{FromRequest, Path, Query, Request, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    middleware,
     response::{IntoResponse, Json, Response},
     routing::{get, post},
     Router,
@@ -11,17 +13,39 @@ use thiserror::Error;
 use tracing::{error, info, instrument};
 
 // Declare modules, making them public
+pub mod binary;
 pub mod config;
+mod events;
+mod format;
+pub mod quantile_tree;
 pub mod segment_tree;
+pub mod sketch_tree;
+pub mod snapshot;
 pub mod store;
+pub mod validation;
+pub mod wal;
+mod ws;
 
-use store::{Store, SymbolStats};
+use config::LimitsConfig;
+use format::{Encoded, ErrorBody, ResponseFormat};
+use store::{QuantileStats, RangeStats, Store, SymbolBatch, SymbolInfo, SymbolStats};
+use validation::InvalidValuePolicy;
 
 // The central, shared application state.
 pub type SharedState = Arc<Store>;
 
-// The maximum size of a batch we can accept in a single request.
-const MAX_BATCH_SIZE: usize = 10000;
+// The number of symbols returned by `GET /symbols` when no limit is given.
+const DEFAULT_SYMBOL_PAGE_SIZE: usize = 100;
+// The largest page `GET /symbols` may return.
+const MAX_SYMBOL_PAGE_SIZE: usize = 1000;
+// The most quantiles a single `/quantiles/` request may ask for.
+const MAX_QUANTILES: usize = 100;
+// The most windows a single `/stats/` request may ask for.
+const MAX_WINDOWS: usize = 32;
+// The most queries a single `/stats/batch` request may contain.
+const MAX_BATCH_QUERIES: usize = 1000;
+// The most symbols' batches a single `/add_batches/` request may contain.
+const MAX_INGEST_BATCHES: usize = 1000;
 
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -31,18 +55,73 @@ pub enum AppError {
     NotEnoughData,
     #[error("Invalid request: {0}")]
     BadRequest(String),
+    #[error(
+        "Prices must be finite and non-negative; invalid values at indices {}",
+        validation::format_indices(.indices)
+    )]
+    InvalidValues { indices: Vec<usize> },
+    #[error("Index range [{start}, {end}] is out of bounds for {len} data points")]
+    IndexOutOfRange {
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+    #[error("Index {start} is no longer retained; the oldest retained index is {first}")]
+    IndexEvicted { start: usize, first: usize },
+    #[error("Persistence error: {0}")]
+    Persistence(#[from] snapshot::SnapshotError),
+    #[error("Write-ahead log error: {0}")]
+    WriteAheadLog(#[from] wal::WalError),
+    /// One batch of a multi-symbol ingestion failed, so none were added.
+    #[error("batches[{index}] ({symbol}): {source}")]
+    BatchRejected {
+        index: usize,
+        symbol: String,
+        source: Box<AppError>,
+    },
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match &self {
+impl AppError {
+    /// The HTTP status and message reported to the client for this error.
+    fn status_and_message(&self) -> (StatusCode, String) {
+        match self {
             AppError::SymbolNotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
             AppError::NotEnoughData => (StatusCode::BAD_REQUEST, self.to_string()),
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
-        };
+            AppError::InvalidValues { .. } => (StatusCode::BAD_REQUEST, self.to_string()),
+            AppError::IndexOutOfRange { .. } => (StatusCode::BAD_REQUEST, self.to_string()),
+            AppError::IndexEvicted { .. } => (StatusCode::BAD_REQUEST, self.to_string()),
+            AppError::Persistence(_) | AppError::WriteAheadLog(_) => {
+                error!(error = %self, "Persistence failure");
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
+            }
+            AppError::BatchRejected {
+                index,
+                symbol,
+                source,
+            } => {
+                let (status, message) = source.status_and_message();
+                (
+                    status,
+                    format!("batches[{}] ({}): {}", index, symbol, message),
+                )
+            }
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = self.status_and_message();
 
-        let body = Json(serde_json::json!({ "error": error_message }));
-        (status, body).into_response()
+        let body = ErrorBody {
+            error: error_message,
+        };
+        // Rendered as JSON here; `format::encode_errors` re-encodes it if the
+        // client asked for another format.
+        let mut response = (status, Json(&body)).into_response();
+        response.extensions_mut().insert(body);
+        response
     }
 }
 
@@ -50,11 +129,133 @@ impl IntoResponse for AppError {
 struct AddBatchRequest {
     symbol: String,
     values: Vec<f64>,
+    /// Optional per-value timestamps (milliseconds since the Unix epoch).
+    timestamps: Option<Vec<u64>>,
+    /// Optional timestamp of the first value; later values are `interval_ms` apart.
+    start_timestamp: Option<u64>,
+    interval_ms: Option<u64>,
+    /// Optional number of points the symbol is expected to hold, so its storage
+    /// can be sized once instead of growing step by step.
+    capacity_hint: Option<usize>,
+    /// Whether invalid values reject the batch (the default) or are dropped.
+    #[serde(default)]
+    on_invalid: InvalidValuePolicy,
+}
+impl From<binary::BinaryBatch> for AddBatchRequest {
+    fn from(batch: binary::BinaryBatch) -> Self {
+        Self {
+            symbol: batch.symbol,
+            values: batch.values,
+            timestamps: batch.timestamps,
+            start_timestamp: None,
+            interval_ms: None,
+            capacity_hint: None,
+            on_invalid: batch.on_invalid,
+        }
+    }
+}
+
+/// An `/add_batch/` body, decoded from the binary format if the request's
+/// `Content-Type` is `binary::BATCH_CONTENT_TYPE` and from JSON otherwise.
+struct BatchPayload(AddBatchRequest);
+
+impl<S> FromRequest<S> for BatchPayload
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_binary = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case(binary::BATCH_CONTENT_TYPE));
+
+        if is_binary {
+            let bytes = Bytes::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            let batch = binary::decode_batch(&bytes).map_err(IntoResponse::into_response)?;
+            Ok(Self(batch.into()))
+        } else {
+            let Json(payload) = Json::<AddBatchRequest>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(payload))
+        }
+    }
+}
+
+/// How a multi-symbol ingestion treats batches that fail validation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum IngestMode {
+    /// Reject the whole request if any batch is invalid.
+    #[default]
+    Atomic,
+    /// Apply every valid batch and report the invalid ones.
+    BestEffort,
+}
+#[derive(Debug, Deserialize)]
+struct AddBatchesRequest {
+    batches: Vec<AddBatchRequest>,
+    #[serde(default)]
+    mode: IngestMode,
 }
 #[derive(Debug, Deserialize)]
 struct StatsRequest {
     symbol: String,
-    exponent: u32,
+    exponent: Option<u32>,
+    window: Option<usize>,
+    /// Inclusive lower time bound, in milliseconds since the Unix epoch.
+    since: Option<u64>,
+    /// Inclusive upper time bound, in milliseconds since the Unix epoch.
+    until: Option<u64>,
+    /// A window length such as `500ms`, `30s`, `5m`, `1h` or `1d`, ending at `until` (or now).
+    duration: Option<String>,
+    /// Delta degrees of freedom for the variance: 0 (population, the default)
+    /// or 1 (unbiased sample variance).
+    ddof: Option<u32>,
+    /// A comma-separated list of window sizes, such as `10,100,1000`, all
+    /// computed over the same version of the data.
+    windows: Option<String>,
+}
+#[derive(Debug, Deserialize)]
+struct BatchStatsQuery {
+    symbol: String,
+    exponent: Option<u32>,
+    window: Option<usize>,
+    ddof: Option<u32>,
+}
+#[derive(Debug, Deserialize)]
+struct BatchStatsRequest {
+    queries: Vec<BatchStatsQuery>,
+}
+#[derive(Debug, Deserialize)]
+struct RangeStatsRequest {
+    symbol: String,
+    start: usize,
+    end: usize,
+}
+#[derive(Debug, Deserialize)]
+struct QuantilesRequest {
+    symbol: String,
+    exponent: Option<u32>,
+    window: Option<usize>,
+    /// A comma-separated list of quantiles in `[0, 1]`, such as `0.5,0.95,0.99`.
+    q: String,
+    /// If set, quantiles are estimated from sketches to within this fraction
+    /// of the window in rank, instead of computed exactly.
+    accuracy: Option<f64>,
+}
+#[derive(Debug, Deserialize)]
+struct SymbolListRequest {
+    /// Only list symbols whose names start with this prefix.
+    prefix: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
 }
 #[derive(Serialize)]
 struct StatsResponse {
@@ -63,6 +264,101 @@ struct StatsResponse {
     last: f64,
     avg: f64,
     var: f64,
+    stddev: f64,
+    ddof: u32,
+    skewness: f64,
+    kurtosis: f64,
+    count: u64,
+    sum: f64,
+    start_index: usize,
+    end_index: usize,
+    truncated: bool,
+}
+
+#[derive(Serialize)]
+struct WindowStatsResponse {
+    /// The window size as requested, before clamping to the retained data.
+    window: usize,
+    #[serde(flatten)]
+    stats: StatsResponse,
+}
+
+#[derive(Serialize)]
+struct MultiWindowStatsResponse {
+    windows: Vec<WindowStatsResponse>,
+}
+
+/// The outcome of one query of a `/stats/batch` request.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BatchStatsOutcome {
+    Stats(StatsResponse),
+    Error { error: String, status: u16 },
+}
+
+impl From<Result<SymbolStats, AppError>> for BatchStatsOutcome {
+    fn from(stats: Result<SymbolStats, AppError>) -> Self {
+        match stats {
+            Ok(stats) => BatchStatsOutcome::Stats(stats.into()),
+            Err(err) => {
+                let (status, error) = err.status_and_message();
+                BatchStatsOutcome::Error {
+                    error,
+                    status: status.as_u16(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchStatsItemResponse {
+    symbol: String,
+    #[serde(flatten)]
+    outcome: BatchStatsOutcome,
+}
+
+/// The outcome of one batch of an `/add_batches/` request.
+#[derive(Serialize)]
+struct IngestItemResponse {
+    symbol: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The indices of invalid values that were dropped.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    dropped: Vec<usize>,
+}
+
+impl IngestItemResponse {
+    fn new(symbol: String, result: Result<Vec<usize>, AppError>) -> Self {
+        let (status, error, dropped) = match result {
+            Ok(dropped) => (StatusCode::OK, None, dropped),
+            Err(err) => {
+                let (status, message) = err.status_and_message();
+                (status, Some(message), Vec::new())
+            }
+        };
+        Self {
+            symbol,
+            status: status.as_u16(),
+            error,
+            dropped,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AddBatchesResponse {
+    status: &'static str,
+    /// The number of batches that were applied.
+    applied: usize,
+    results: Vec<IngestItemResponse>,
+}
+
+#[derive(Serialize)]
+struct BatchStatsResponse {
+    results: Vec<BatchStatsItemResponse>,
 }
 
 // Implement conversion from our internal stats struct to the web response.
@@ -74,74 +370,658 @@ impl From<SymbolStats> for StatsResponse {
             last: stats.last,
             avg: stats.avg,
             var: stats.var,
+            stddev: stats.stddev(),
+            ddof: stats.ddof,
+            skewness: stats.skewness,
+            kurtosis: stats.kurtosis,
+            count: stats.count,
+            sum: stats.sum,
+            start_index: stats.start_index,
+            end_index: stats.end_index,
+            truncated: stats.truncated,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RangeStatsResponse {
+    min: f64,
+    max: f64,
+    avg: f64,
+    var: f64,
+    count: u64,
+}
+
+impl From<RangeStats> for RangeStatsResponse {
+    fn from(stats: RangeStats) -> Self {
+        Self {
+            min: stats.min,
+            max: stats.max,
+            avg: stats.avg,
+            var: stats.var,
+            count: stats.count,
         }
     }
 }
 
+#[derive(Serialize)]
+struct QuantileValue {
+    q: f64,
+    value: f64,
+}
+
+#[derive(Serialize)]
+struct QuantilesResponse {
+    count: u64,
+    quantiles: Vec<QuantileValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rank_error: Option<f64>,
+}
+
+impl From<QuantileStats> for QuantilesResponse {
+    fn from(stats: QuantileStats) -> Self {
+        Self {
+            count: stats.count,
+            quantiles: stats
+                .quantiles
+                .into_iter()
+                .map(|(q, value)| QuantileValue { q, value })
+                .collect(),
+            rank_error: stats.rank_error,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SymbolInfoResponse {
+    symbol: String,
+    points: usize,
+    retained: usize,
+    first_index: usize,
+    capacity: usize,
+    retention: Option<usize>,
+    first_value: Option<f64>,
+    last_value: Option<f64>,
+    created_at: u64,
+    updated_at: u64,
+    memory_bytes: usize,
+}
+
+impl From<SymbolInfo> for SymbolInfoResponse {
+    fn from(info: SymbolInfo) -> Self {
+        Self {
+            symbol: info.symbol,
+            points: info.points,
+            retained: info.retained,
+            first_index: info.first_index,
+            capacity: info.capacity,
+            retention: info.retention,
+            first_value: info.first_value,
+            last_value: info.last_value,
+            created_at: info.created_at,
+            updated_at: info.updated_at,
+            memory_bytes: info.memory_bytes,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SymbolListResponse {
+    symbols: Vec<SymbolInfoResponse>,
+    /// The number of symbols matching the prefix, across all pages.
+    total: usize,
+    offset: usize,
+    limit: usize,
+}
+
 pub fn app_router(state: SharedState) -> Router {
     Router::new()
         .route("/health", get(health_check_handler))
         .route("/add_batch/", post(add_batch_handler))
+        .route("/add_batches/", post(add_batches_handler))
         .route("/stats/", get(get_stats_handler))
+        .route("/stats/batch", post(batch_stats_handler))
+        .route("/range_stats/", get(get_range_stats_handler))
+        .route("/quantiles/", get(get_quantiles_handler))
+        .route("/symbols", get(list_symbols_handler))
+        .route(
+            "/symbols/{symbol}",
+            get(symbol_info_handler).delete(remove_symbol_handler),
+        )
+        .route("/symbols/{symbol}/reset", post(reset_symbol_handler))
+        .route("/admin/snapshot", post(snapshot_handler))
+        .route("/ws/stats", get(ws::stats_ws_handler))
+        .route("/ws/ingest", get(ws::ingest_ws_handler))
+        .route("/events", get(events::events_handler))
+        .layer(middleware::from_fn(format::encode_errors))
         .with_state(state)
 }
 
 #[instrument(name = "health_check")]
-async fn health_check_handler() -> impl IntoResponse {
+async fn health_check_handler(format: ResponseFormat) -> impl IntoResponse {
     info!("Health check successful");
-    (StatusCode::OK, Json(serde_json::json!({"status": "ok"})))
+    Encoded(format, serde_json::json!({"status": "ok"}))
 }
 
 #[instrument(name = "add_batch_request", skip(state, payload), fields(symbol = %payload.symbol, count = payload.values.len()))]
 async fn add_batch_handler(
     State(state): State<SharedState>,
-    Json(payload): Json<AddBatchRequest>,
+    format: ResponseFormat,
+    BatchPayload(payload): BatchPayload,
 ) -> Result<impl IntoResponse, AppError> {
-    if payload.values.is_empty() {
-        return Err(AppError::BadRequest(
-            "Cannot add an empty batch of values".to_string(),
-        ));
+    let dropped = apply_batch(&state, &payload)?;
+
+    info!(dropped = dropped.len(), "Successfully added batch");
+    let body = if dropped.is_empty() {
+        serde_json::json!({ "status": "success" })
+    } else {
+        serde_json::json!({ "status": "success", "dropped": dropped })
+    };
+    Ok(Encoded(format, body))
+}
+
+/// Adds a single batch request to the store, returning the indices of the
+/// values that were dropped.
+fn apply_batch(state: &Store, payload: &AddBatchRequest) -> Result<Vec<usize>, AppError> {
+    let timestamps = prepare_batch(state.limits(), payload)?;
+
+    // The store validates the values, and stamps them if there are no timestamps.
+    state.add_symbol_batch(&symbol_batch(payload, timestamps.as_deref()))
+}
+
+/// The store's view of a batch request, with its resolved timestamps.
+fn symbol_batch<'a>(
+    payload: &'a AddBatchRequest,
+    timestamps: Option<&'a [u64]>,
+) -> SymbolBatch<'a> {
+    SymbolBatch {
+        symbol: &payload.symbol,
+        values: &payload.values,
+        timestamps,
+        policy: payload.on_invalid,
+        capacity_hint: payload.capacity_hint,
     }
+}
 
-    if payload.values.len() > MAX_BATCH_SIZE {
+#[instrument(name = "add_batches_request", skip(state, payload), fields(batches = payload.batches.len(), mode = ?payload.mode))]
+async fn add_batches_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+    Json(payload): Json<AddBatchesRequest>,
+) -> Result<Encoded<AddBatchesResponse>, AppError> {
+    if payload.batches.len() > MAX_INGEST_BATCHES {
         return Err(AppError::BadRequest(format!(
-            "Batch size cannot exceed {} values.",
-            MAX_BATCH_SIZE
+            "At most {} batches may be sent at once",
+            MAX_INGEST_BATCHES
         )));
     }
 
-    if payload.values.iter().any(|&v| v < 0.0) {
+    let results: Vec<IngestItemResponse> = match payload.mode {
+        IngestMode::Atomic => {
+            let timestamps = payload
+                .batches
+                .iter()
+                .enumerate()
+                .map(|(index, batch)| {
+                    prepare_batch(state.limits(), batch).map_err(|source| AppError::BatchRejected {
+                        index,
+                        symbol: batch.symbol.clone(),
+                        source: Box::new(source),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let batches: Vec<SymbolBatch> = payload
+                .batches
+                .iter()
+                .zip(&timestamps)
+                .map(|(batch, timestamps)| symbol_batch(batch, timestamps.as_deref()))
+                .collect();
+
+            // Either every batch is added or the request fails as a whole.
+            let dropped = state.add_batches_atomic(&batches)?;
+            payload
+                .batches
+                .iter()
+                .zip(dropped)
+                .map(|(batch, dropped)| IngestItemResponse::new(batch.symbol.clone(), Ok(dropped)))
+                .collect()
+        }
+        IngestMode::BestEffort => payload
+            .batches
+            .iter()
+            .map(|batch| IngestItemResponse::new(batch.symbol.clone(), apply_batch(&state, batch)))
+            .collect(),
+    };
+    let applied = results
+        .iter()
+        .filter(|result| result.error.is_none())
+        .count();
+
+    info!(applied, "Successfully added batches");
+    Ok(Encoded(
+        format,
+        AddBatchesResponse {
+            status: if applied == results.len() {
+                "success"
+            } else {
+                "partial"
+            },
+            applied,
+            results,
+        },
+    ))
+}
+
+/// Checks the parts of a batch that only exist over HTTP (the capacity hint and
+/// the forms timestamps can take), returning its explicit timestamps if it has
+/// any. The values themselves are validated by the store.
+fn prepare_batch(
+    limits: &LimitsConfig,
+    payload: &AddBatchRequest,
+) -> Result<Option<Vec<u64>>, AppError> {
+    let max_capacity_hint = limits.max_capacity_hint;
+    if payload
+        .capacity_hint
+        .is_some_and(|hint| hint > max_capacity_hint)
+    {
+        return Err(AppError::BadRequest(format!(
+            "capacity_hint cannot exceed {}",
+            max_capacity_hint
+        )));
+    }
+
+    resolve_batch_timestamps(payload)
+}
+
+/// Resolves the timestamps for a batch from either explicit per-value `timestamps`
+/// or a `start_timestamp` plus `interval_ms`. Returns `None` when neither is given,
+/// in which case the store stamps the batch with the current time.
+fn resolve_batch_timestamps(payload: &AddBatchRequest) -> Result<Option<Vec<u64>>, AppError> {
+    match (
+        &payload.timestamps,
+        payload.start_timestamp,
+        payload.interval_ms,
+    ) {
+        (None, None, None) => Ok(None),
+        (Some(timestamps), None, None) => Ok(Some(timestamps.clone())),
+        (None, Some(start), Some(interval)) => Ok(Some(
+            (0..payload.values.len() as u64)
+                .map(|i| start.saturating_add(i.saturating_mul(interval)))
+                .collect(),
+        )),
+        (Some(_), _, _) => Err(AppError::BadRequest(
+            "timestamps cannot be combined with start_timestamp or interval_ms".to_string(),
+        )),
+        (None, _, _) => Err(AppError::BadRequest(
+            "start_timestamp and interval_ms must be provided together".to_string(),
+        )),
+    }
+}
+
+/// Parses a duration such as `500ms`, `30s`, `5m`, `1h` or `1d` into milliseconds.
+fn parse_duration_ms(duration: &str) -> Result<u64, AppError> {
+    let invalid = || {
+        AppError::BadRequest(format!(
+            "Invalid duration '{}': expected a number followed by ms, s, m, h or d",
+            duration
+        ))
+    };
+
+    let split_at = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = duration.split_at(split_at);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let unit_ms = match unit {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        _ => return Err(invalid()),
+    };
+
+    amount.checked_mul(unit_ms).ok_or_else(invalid)
+}
+
+/// Resolves the inclusive `[since, until]` time range of a time-based stats request.
+/// `duration` counts back from `until`, which defaults to now.
+fn resolve_time_range(
+    since: Option<u64>,
+    until: Option<u64>,
+    duration: Option<&str>,
+) -> Result<(u64, u64), AppError> {
+    let (since, until) = match (since, duration) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::BadRequest(
+                "Only one of since or duration may be provided".to_string(),
+            ))
+        }
+        (_, Some(duration)) => {
+            let until = until.unwrap_or_else(store::now_millis);
+            (until.saturating_sub(parse_duration_ms(duration)?), until)
+        }
+        (since, None) => (since.unwrap_or(0), until.unwrap_or(u64::MAX)),
+    };
+
+    if since > until {
         return Err(AppError::BadRequest(
-            "Negative trading prices are not allowed".to_string(),
+            "since must not be later than until".to_string(),
         ));
     }
+    Ok((since, until))
+}
 
-    // The handler now just delegates to the store.
-    state.add_batch(&payload.symbol, &payload.values)?;
+/// Resolves the requested window size from either `exponent` (10^exponent points)
+/// or an explicit `window`. Exactly one of the two must be provided.
+fn resolve_window_size(
+    limits: &LimitsConfig,
+    exponent: Option<u32>,
+    window: Option<usize>,
+) -> Result<usize, AppError> {
+    match (exponent, window) {
+        (Some(_), Some(_)) => Err(AppError::BadRequest(
+            "Only one of exponent or window may be provided".to_string(),
+        )),
+        (None, None) => Err(AppError::BadRequest(
+            "Either exponent or window must be provided".to_string(),
+        )),
+        (Some(exponent), None) => {
+            if !(limits.min_exponent..=limits.max_exponent).contains(&exponent) {
+                return Err(AppError::BadRequest(format!(
+                    "exponent must be an integer between {} and {}",
+                    limits.min_exponent, limits.max_exponent
+                )));
+            }
+            Ok(10_usize.checked_pow(exponent).unwrap_or(usize::MAX))
+        }
+        (None, Some(window)) => {
+            if !(1..=limits.max_window).contains(&window) {
+                return Err(AppError::BadRequest(format!(
+                    "window must be an integer between 1 and {}",
+                    limits.max_window
+                )));
+            }
+            Ok(window)
+        }
+    }
+}
 
-    info!("Successfully added batch");
-    Ok((
-        StatusCode::OK,
-        Json(serde_json::json!({ "status": "success" })),
-    ))
+/// Validates a `ddof` parameter, which defaults to 0.
+fn resolve_ddof(ddof: Option<u32>) -> Result<u32, AppError> {
+    match ddof.unwrap_or(0) {
+        ddof @ (0 | 1) => Ok(ddof),
+        _ => Err(AppError::BadRequest("ddof must be 0 or 1".to_string())),
+    }
 }
 
-#[instrument(name = "get_stats_request", skip(state), fields(symbol = %params.symbol, exponent = %params.exponent))]
+/// Parses a comma-separated list of window sizes, each within the configured limits.
+fn parse_windows(limits: &LimitsConfig, windows: &str) -> Result<Vec<usize>, AppError> {
+    if windows.split(',').count() > MAX_WINDOWS {
+        return Err(AppError::BadRequest(format!(
+            "At most {} windows may be requested at once",
+            MAX_WINDOWS
+        )));
+    }
+
+    windows
+        .split(',')
+        .map(|part| {
+            let window = part.trim().parse::<usize>().map_err(|_| {
+                AppError::BadRequest(format!(
+                    "Invalid window '{}': expected a positive integer",
+                    part
+                ))
+            })?;
+            resolve_window_size(limits, None, Some(window))
+        })
+        .collect()
+}
+
+#[instrument(name = "get_stats_request", skip(state), fields(symbol = %params.symbol, exponent = ?params.exponent, window = ?params.window, windows = ?params.windows, since = ?params.since, until = ?params.until, duration = ?params.duration, ddof = ?params.ddof))]
 async fn get_stats_handler(
     State(state): State<SharedState>,
+    format: ResponseFormat,
     Query(params): Query<StatsRequest>,
-) -> Result<Json<StatsResponse>, AppError> {
-    if !(1..=8).contains(&params.exponent) {
-        return Err(AppError::BadRequest(
-            "exponent must be an integer between 1 and 8".to_string(),
-        ));
-    }
+) -> Result<Response, AppError> {
+    let ddof = resolve_ddof(params.ddof)?;
+    let is_time_window =
+        params.since.is_some() || params.until.is_some() || params.duration.is_some();
+
+    if let Some(windows) = params.windows.as_deref() {
+        if is_time_window || params.exponent.is_some() || params.window.is_some() {
+            return Err(AppError::BadRequest(
+                "windows cannot be combined with exponent, window, since, until or duration"
+                    .to_string(),
+            ));
+        }
+        let window_sizes = parse_windows(state.limits(), windows)?;
+        let all_stats = state.get_stats_for_windows(&params.symbol, &window_sizes)?;
+
+        let windows = window_sizes
+            .into_iter()
+            .zip(all_stats)
+            .map(|(window, stats)| {
+                Ok(WindowStatsResponse {
+                    window,
+                    stats: stats.with_ddof(ddof)?.into(),
+                })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
 
-    let window_size = 10_u64.pow(params.exponent) as usize;
+        info!("Successfully retrieved stats for multiple windows");
+        return Ok(Encoded(format, MultiWindowStatsResponse { windows }).into_response());
+    }
 
     // The handler delegates and then converts the result to the response type.
-    let stats = state.get_stats(&params.symbol, window_size)?;
+    let stats = if is_time_window {
+        if params.exponent.is_some() || params.window.is_some() {
+            return Err(AppError::BadRequest(
+                "exponent and window cannot be combined with since, until or duration".to_string(),
+            ));
+        }
+        let (since, until) =
+            resolve_time_range(params.since, params.until, params.duration.as_deref())?;
+        state.get_stats_between(&params.symbol, since, until)?
+    } else {
+        let window_size = resolve_window_size(state.limits(), params.exponent, params.window)?;
+        state.get_stats(&params.symbol, window_size)?
+    };
+    let stats = stats.with_ddof(ddof)?;
 
     info!("Successfully retrieved stats");
-    Ok(Json(stats.into()))
+    Ok(Encoded(format, StatsResponse::from(stats)).into_response())
+}
+
+#[instrument(name = "batch_stats_request", skip(state, payload), fields(count = payload.queries.len()))]
+async fn batch_stats_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+    Json(payload): Json<BatchStatsRequest>,
+) -> Result<Encoded<BatchStatsResponse>, AppError> {
+    if payload.queries.len() > MAX_BATCH_QUERIES {
+        return Err(AppError::BadRequest(format!(
+            "At most {} queries may be sent at once",
+            MAX_BATCH_QUERIES
+        )));
+    }
+
+    // Each query succeeds or fails on its own, so one unknown symbol does not
+    // hide the results for the rest.
+    let results = payload
+        .queries
+        .into_iter()
+        .map(|query| {
+            let stats = resolve_window_size(state.limits(), query.exponent, query.window)
+                .and_then(|window_size| state.get_stats(&query.symbol, window_size))
+                .and_then(|stats| stats.with_ddof(resolve_ddof(query.ddof)?));
+            BatchStatsItemResponse {
+                symbol: query.symbol,
+                outcome: stats.into(),
+            }
+        })
+        .collect();
+
+    info!("Successfully processed batch stats");
+    Ok(Encoded(format, BatchStatsResponse { results }))
+}
+
+#[instrument(name = "get_range_stats_request", skip(state), fields(symbol = %params.symbol, start = %params.start, end = %params.end))]
+async fn get_range_stats_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+    Query(params): Query<RangeStatsRequest>,
+) -> Result<Encoded<RangeStatsResponse>, AppError> {
+    let stats = state.get_range_stats(&params.symbol, params.start, params.end)?;
+
+    info!("Successfully retrieved range stats");
+    Ok(Encoded(format, stats.into()))
+}
+
+/// Parses a comma-separated list of quantiles, each within `[0, 1]`.
+fn parse_quantiles(q: &str) -> Result<Vec<f64>, AppError> {
+    let qs = q
+        .split(',')
+        .map(|part| {
+            part.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|q| (0.0..=1.0).contains(q))
+                .ok_or_else(|| {
+                    AppError::BadRequest(format!(
+                        "Invalid quantile '{}': expected a number between 0 and 1",
+                        part
+                    ))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if qs.len() > MAX_QUANTILES {
+        return Err(AppError::BadRequest(format!(
+            "At most {} quantiles may be requested at once",
+            MAX_QUANTILES
+        )));
+    }
+    Ok(qs)
+}
+
+#[instrument(name = "get_quantiles_request", skip(state), fields(symbol = %params.symbol, exponent = ?params.exponent, window = ?params.window, q = %params.q, accuracy = ?params.accuracy))]
+async fn get_quantiles_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+    Query(params): Query<QuantilesRequest>,
+) -> Result<Encoded<QuantilesResponse>, AppError> {
+    let window_size = resolve_window_size(state.limits(), params.exponent, params.window)?;
+    let qs = parse_quantiles(&params.q)?;
+
+    let quantiles = match params.accuracy {
+        Some(accuracy) => {
+            if !(sketch_tree::MIN_ACCURACY..=1.0).contains(&accuracy) {
+                return Err(AppError::BadRequest(format!(
+                    "accuracy must be between {} and 1",
+                    sketch_tree::MIN_ACCURACY
+                )));
+            }
+            state.get_approx_quantiles(&params.symbol, window_size, &qs, accuracy)?
+        }
+        None => state.get_quantiles(&params.symbol, window_size, &qs)?,
+    };
+
+    info!("Successfully retrieved quantiles");
+    Ok(Encoded(format, quantiles.into()))
+}
+
+#[instrument(name = "list_symbols_request", skip(state))]
+async fn list_symbols_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+    Query(params): Query<SymbolListRequest>,
+) -> Result<Encoded<SymbolListResponse>, AppError> {
+    let offset = params.offset.unwrap_or(0);
+    let limit = params.limit.unwrap_or(DEFAULT_SYMBOL_PAGE_SIZE);
+    if !(1..=MAX_SYMBOL_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be an integer between 1 and {}",
+            MAX_SYMBOL_PAGE_SIZE
+        )));
+    }
+
+    let prefix = params.prefix.as_deref().unwrap_or("");
+    let (symbols, total) = state.list_symbols(prefix, offset, limit);
+
+    info!(
+        total,
+        returned = symbols.len(),
+        "Successfully listed symbols"
+    );
+    Ok(Encoded(
+        format,
+        SymbolListResponse {
+            symbols: symbols.into_iter().map(Into::into).collect(),
+            total,
+            offset,
+            limit,
+        },
+    ))
+}
+
+#[instrument(name = "symbol_info_request", skip(state))]
+async fn symbol_info_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+    Path(symbol): Path<String>,
+) -> Result<Encoded<SymbolInfoResponse>, AppError> {
+    let info = state.symbol_info(&symbol)?;
+
+    info!("Successfully described symbol");
+    Ok(Encoded(format, info.into()))
+}
+
+#[instrument(name = "remove_symbol_request", skip(state))]
+async fn remove_symbol_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+    Path(symbol): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    state.remove_symbol(&symbol)?;
+
+    info!("Successfully removed symbol");
+    Ok(Encoded(format, serde_json::json!({ "status": "success" })))
+}
+
+#[instrument(name = "reset_symbol_request", skip(state))]
+async fn reset_symbol_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+    Path(symbol): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    state.reset_symbol(&symbol)?;
+
+    info!("Successfully reset symbol");
+    Ok(Encoded(format, serde_json::json!({ "status": "success" })))
+}
+
+#[instrument(name = "snapshot_request", skip(state))]
+async fn snapshot_handler(
+    State(state): State<SharedState>,
+    format: ResponseFormat,
+) -> Result<impl IntoResponse, AppError> {
+    // Snapshots do blocking file I/O, so keep them off the async worker threads.
+    let summary = tokio::task::spawn_blocking(move || state.save_snapshot())
+        .await
+        .map_err(|e| snapshot::SnapshotError::Io(std::io::Error::other(e)))??;
+
+    info!(
+        symbols = summary.symbols,
+        points = summary.points,
+        "Successfully wrote snapshot"
+    );
+    Ok(Encoded(
+        format,
+        serde_json::json!({
+            "status": "success",
+            "symbols": summary.symbols,
+            "points": summary.points,
+        }),
+    ))
 }
@@ -1,5 +1,12 @@
-use hft_service::{app_router, config::Config, store::Store, SharedState};
+use hft_service::{
+    app_router,
+    config::Config,
+    store::Store,
+    wal::{FsyncPolicy, Wal},
+    SharedState,
+};
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::signal;
 use tracing::{error, info};
@@ -32,10 +39,61 @@ async fn main() {
         .init();
 
     // Create shared state
-    let state = SharedState::new(Store::new());
+    let mut store = Store::new()
+        .with_limits(config.limits.clone())
+        .with_retention(config.retention.clone());
+    if config.snapshot.enabled {
+        store = store.with_snapshot_path(&config.snapshot.path);
+    }
+    if config.wal.enabled {
+        match Wal::open(&config.wal.dir, config.wal.fsync_policy()) {
+            Ok(wal) => store = store.with_wal(wal),
+            Err(e) => {
+                error!(error = %e, "Failed to open write-ahead log");
+                std::process::exit(1);
+            }
+        }
+    }
+    let state = SharedState::new(store);
+
+    // Restore the last snapshot before accepting any traffic. Refuse to start on a
+    // corrupt snapshot rather than silently overwriting it with an empty store.
+    match state.load_snapshot() {
+        Ok(Some(summary)) => {
+            info!(
+                symbols = summary.symbols,
+                points = summary.points,
+                "Restored store from snapshot"
+            )
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!(error = %e, "Failed to load snapshot");
+            std::process::exit(1);
+        }
+    }
+
+    // Re-apply batches added since the snapshot was taken.
+    if let Err(e) = state.replay_wal() {
+        error!(error = %e, "Failed to replay write-ahead log");
+        std::process::exit(1);
+    }
+
+    if let FsyncPolicy::Interval(period) = config.wal.fsync_policy() {
+        if config.wal.enabled {
+            tokio::spawn(periodic_wal_sync(state.clone(), period));
+        }
+    }
+
+    if config.snapshot.enabled && config.snapshot.interval_secs > 0 {
+        tokio::spawn(periodic_snapshots(
+            state.clone(),
+            Duration::from_secs(config.snapshot.interval_secs),
+        ));
+    }
 
     // Create the Axum router from the library
-    let app = app_router(state);
+    let app = app_router(state.clone());
 
     // Start the server
     let addr_str = format!("{}:{}", config.server.host, config.server.port);
@@ -57,16 +115,64 @@ async fn main() {
         }
     };
 
+    // Graceful shutdown waits for every connection to finish, so push streams
+    // are told to end as soon as the signal arrives.
+    let streams = state.clone();
     if let Err(e) = axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            streams.close_streams();
+        })
         .await
     {
         error!(error = %e, "Server error");
     }
 
+    // All in-flight requests have completed, so this snapshot captures every
+    // acknowledged batch.
+    if config.snapshot.enabled {
+        if let Err(e) = state.save_snapshot() {
+            error!(error = %e, "Failed to write shutdown snapshot");
+        }
+    }
+    if let Err(e) = state.sync_wal() {
+        error!(error = %e, "Failed to sync write-ahead log");
+    }
+
     info!("Server has shut down gracefully");
 }
 
+async fn periodic_snapshots(state: SharedState, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    // The first tick completes immediately; there is nothing new to save yet.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        match tokio::task::spawn_blocking(move || state.save_snapshot()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => error!(error = %e, "Periodic snapshot failed"),
+            Err(e) => error!(error = %e, "Periodic snapshot task panicked"),
+        }
+    }
+}
+
+/// Syncs the write-ahead log on a timer, so batches written during quiet periods
+/// don't wait for the next batch to become durable.
+async fn periodic_wal_sync(state: SharedState, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        match tokio::task::spawn_blocking(move || state.sync_wal()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!(error = %e, "Periodic write-ahead log sync failed"),
+            Err(e) => error!(error = %e, "Periodic write-ahead log sync task panicked"),
+        }
+    }
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         if let Err(e) = signal::ctrl_c().await {
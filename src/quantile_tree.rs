@@ -0,0 +1,263 @@
+use std::mem;
+
+/// The number of bits of fan-out between levels: every chunk is made of 16
+/// chunks of the level below it.
+pub(crate) const FANOUT_BITS: u32 = 4;
+
+/// A merge-sort tree answering order-statistics queries (how many values in a
+/// range are at most `x`, and therefore the k-th smallest value) over the same
+/// slots as a `SegmentTree`.
+///
+/// Level `j` splits the slots into aligned chunks of `16^j` and keeps each chunk
+/// sorted, stored in place in a single array per level. Level 0 is the values
+/// themselves. A chunk is sorted when its last slot is written, so appends cost
+/// amortized O(log N), and a range is covered by O(log N) chunks, each searched
+/// in O(log N).
+///
+/// Slots may be overwritten (as by a ring buffer) as long as they are written in
+/// order: a chunk is only stale between the write of its first and last slot,
+/// and a range that ends at the most recently written slot never covers it.
+#[derive(Default)]
+pub struct QuantileTree {
+    /// `levels[j - 1]` holds the sorted chunks of level `j`, as order-preserving keys.
+    levels: Vec<Vec<u64>>,
+}
+
+impl QuantileTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the tree over `values`, sorting every complete chunk.
+    pub fn from_values(values: &[f64]) -> Self {
+        let mut tree = Self::new();
+        tree.update(0, values.len(), values);
+        tree
+    }
+
+    /// Re-sorts every chunk that ends within the `count` slots starting at
+    /// `start`, which `all_values` must already contain.
+    pub fn update(&mut self, start: usize, count: usize, all_values: &[f64]) {
+        let end = start + count;
+        for level in 1..=self.levels.len() {
+            self.levels[level - 1].resize(all_values.len(), 0);
+
+            // Lower levels are updated first, so each chunk merges fresh runs.
+            let size = chunk_size(level);
+            let mut chunk_start = start / size * size;
+            while chunk_start + size <= end {
+                self.sort_chunk(level, chunk_start, all_values);
+                chunk_start += size;
+            }
+        }
+
+        // Add levels whose chunks now fit in the slots.
+        while chunk_size(self.levels.len() + 1) <= all_values.len() {
+            self.levels.push(vec![0; all_values.len()]);
+            let level = self.levels.len();
+            let size = chunk_size(level);
+            for chunk_start in (0..=all_values.len() - size).step_by(size) {
+                self.sort_chunk(level, chunk_start, all_values);
+            }
+        }
+    }
+
+    /// Sorts the chunk of `level` starting at `chunk_start`, by merging the
+    /// already sorted chunks of the level below it.
+    fn sort_chunk(&mut self, level: usize, chunk_start: usize, all_values: &[f64]) {
+        let range = chunk_start..chunk_start + chunk_size(level);
+        if level == 1 {
+            let chunk = &mut self.levels[0][range.clone()];
+            for (key, &value) in chunk.iter_mut().zip(&all_values[range]) {
+                *key = to_key(value);
+            }
+            chunk.sort_unstable();
+        } else {
+            let (lower, upper) = self.levels.split_at_mut(level - 1);
+            let chunk = &mut upper[0][range.clone()];
+            chunk.copy_from_slice(&lower[level - 2][range]);
+            // The chunk is made of 16 sorted runs, which a stable sort merges
+            // in O(n log 16).
+            chunk.sort();
+        }
+    }
+
+    /// The number of bytes allocated for the sorted chunks.
+    pub fn memory_footprint(&self) -> usize {
+        self.levels
+            .iter()
+            .map(|level| level.capacity() * mem::size_of::<u64>())
+            .sum()
+    }
+
+    /// Counts the values at most `key` in the slots `[left, right]`.
+    fn count_at_most(&self, left: usize, right: usize, key: u64, all_values: &[f64]) -> usize {
+        let (mut low, mut high) = (left, right + 1);
+        let mut count = 0;
+
+        // Climb the levels, consuming chunks from both ends until the remaining
+        // range is aligned to the next level. The top level consumes the rest.
+        for level in 0..=self.levels.len() {
+            let size = chunk_size(level);
+            let is_top = level == self.levels.len();
+            let next = size << FANOUT_BITS;
+
+            while low < high && (is_top || low % next != 0) && low + size <= high {
+                count += self.count_in_chunk(level, low, key, all_values);
+                low += size;
+            }
+            while low < high && (is_top || high % next != 0) && high - size >= low {
+                count += self.count_in_chunk(level, high - size, key, all_values);
+                high -= size;
+            }
+            if low >= high {
+                break;
+            }
+        }
+        count
+    }
+
+    fn count_in_chunk(
+        &self,
+        level: usize,
+        chunk_start: usize,
+        key: u64,
+        all_values: &[f64],
+    ) -> usize {
+        if level == 0 {
+            return usize::from(to_key(all_values[chunk_start]) <= key);
+        }
+        self.levels[level - 1][chunk_start..chunk_start + chunk_size(level)]
+            .partition_point(|&k| k <= key)
+    }
+
+    /// Returns the `rank`-th smallest value (0-based) across the slot ranges
+    /// `ranges` (each `[left, right]`, inclusive), which must hold more than
+    /// `rank` values in total.
+    pub fn select(&self, ranges: &[(usize, usize)], rank: usize, all_values: &[f64]) -> f64 {
+        // Find the smallest key with more than `rank` values at or below it.
+        // That key always belongs to a value in the ranges.
+        let (mut low, mut high) = (0u64, u64::MAX);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let count: usize = ranges
+                .iter()
+                .map(|&(left, right)| self.count_at_most(left, right, mid, all_values))
+                .sum();
+            if count > rank {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        from_key(low)
+    }
+}
+
+pub(crate) fn chunk_size(level: usize) -> usize {
+    1 << (level as u32 * FANOUT_BITS)
+}
+
+/// Maps a float to an integer with the same total order.
+pub(crate) fn to_key(value: f64) -> u64 {
+    let bits = value.to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | (1 << 63)
+    }
+}
+
+/// The inverse of `to_key`.
+pub(crate) fn from_key(key: u64) -> f64 {
+    if key >> 63 == 1 {
+        f64::from_bits(key & !(1 << 63))
+    } else {
+        f64::from_bits(!key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the sorted values in the slots `[left, right]`.
+    fn brute_force_sorted(values: &[f64], left: usize, right: usize) -> Vec<f64> {
+        let mut sorted = values[left..=right].to_vec();
+        sorted.sort_by(f64::total_cmp);
+        sorted
+    }
+
+    #[test]
+    fn test_keys_preserve_order() {
+        let values = [-1e300, -2.5, -0.0, 0.0, 1e-300, 3.0, f64::MAX];
+        for pair in values.windows(2) {
+            assert!(to_key(pair[0]) < to_key(pair[1]));
+        }
+        for value in values {
+            assert_eq!(from_key(to_key(value)).to_bits(), value.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_select_matches_sorting() {
+        // Arrange: Enough values for three levels, appended in uneven batches.
+        let values: Vec<f64> = (0..5000)
+            .map(|i| ((i * 7919) % 1013) as f64 / 4.0)
+            .collect();
+        let mut tree = QuantileTree::new();
+        let mut written = 0;
+        for batch in [1, 15, 16, 300, 2000, 2668] {
+            tree.update(written, batch, &values[..written + batch]);
+            written += batch;
+        }
+
+        // Act & Assert: Every rank of assorted ranges matches a sorted copy.
+        for (left, right) in [
+            (0, 4999),
+            (4990, 4999),
+            (17, 4000),
+            (255, 256),
+            (1234, 1234),
+        ] {
+            let sorted = brute_force_sorted(&values, left, right);
+            for rank in [0, sorted.len() / 4, sorted.len() / 2, sorted.len() - 1] {
+                assert_eq!(
+                    tree.select(&[(left, right)], rank, &values),
+                    sorted[rank],
+                    "rank {} of [{}, {}]",
+                    rank,
+                    left,
+                    right
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_after_overwrites() {
+        // Arrange: A 100-slot ring buffer written for several laps.
+        let limit = 100;
+        let mut slots = vec![0.0; limit];
+        let mut tree = QuantileTree::new();
+        let mut next_index = 0;
+        for batch in [70, 45, 99, 3, 120] {
+            for _ in 0..batch {
+                slots[next_index % limit] = ((next_index * 37) % 101) as f64;
+                tree.update(next_index % limit, 1, &slots);
+                next_index += 1;
+            }
+        }
+
+        // Act: The retained range wraps, so it is queried as two slot ranges.
+        let head = (next_index - 1) % limit;
+        let ranges = [(head + 1, limit - 1), (0, head)];
+        let mut expected: Vec<f64> = slots.clone();
+        expected.sort_by(f64::total_cmp);
+
+        // Assert
+        for (rank, &value) in expected.iter().enumerate() {
+            assert_eq!(tree.select(&ranges, rank, &slots), value);
+        }
+    }
+}
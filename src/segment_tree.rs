@@ -10,9 +10,12 @@ pub struct Node {
     pub max: f64,
     pub count: u64,
 
-    // Fields for Welford's algorithm
+    // Fields for Welford's algorithm, extended to the third and fourth central
+    // moments: `m2`, `m3` and `m4` are the sums of (x - mean)^2, ^3 and ^4.
     pub mean: f64,
     pub m2: f64,
+    pub m3: f64,
+    pub m4: f64,
 }
 
 impl Default for Node {
@@ -23,10 +26,31 @@ impl Default for Node {
             count: 0,
             mean: 0.0,
             m2: 0.0,
+            m3: 0.0,
+            m4: 0.0,
         }
     }
 }
 
+impl Node {
+    /// The population skewness, or 0 if all values are equal.
+    pub fn skewness(&self) -> f64 {
+        if self.m2 <= 0.0 {
+            return 0.0;
+        }
+        (self.count as f64).sqrt() * self.m3 / self.m2.powf(1.5)
+    }
+
+    /// The population excess kurtosis (0 for a normal distribution), or 0 if all
+    /// values are equal.
+    pub fn excess_kurtosis(&self) -> f64 {
+        if self.m2 <= 0.0 {
+            return 0.0;
+        }
+        self.count as f64 * self.m4 / (self.m2 * self.m2) - 3.0
+    }
+}
+
 /// Defines how to merge two child nodes into a parent node.
 /// This operation is the core of the tree's aggregation logic.
 impl Add for Node {
@@ -49,12 +73,27 @@ impl Add for Node {
             + rhs.m2
             + delta.powi(2) * (self.count as f64 * rhs.count as f64 / combined_count as f64);
 
+        // The higher moments follow Pébay's generalization of the same pairwise
+        // update. Each correction is computed from the inputs' lower moments.
+        let (n_a, n_b, n) = (self.count as f64, rhs.count as f64, combined_count as f64);
+        let new_m3 = self.m3
+            + rhs.m3
+            + delta.powi(3) * n_a * n_b * (n_a - n_b) / (n * n)
+            + 3.0 * delta * (n_a * rhs.m2 - n_b * self.m2) / n;
+        let new_m4 = self.m4
+            + rhs.m4
+            + delta.powi(4) * n_a * n_b * (n_a * n_a - n_a * n_b + n_b * n_b) / (n * n * n)
+            + 6.0 * delta.powi(2) * (n_a * n_a * rhs.m2 + n_b * n_b * self.m2) / (n * n)
+            + 4.0 * delta * (n_a * rhs.m3 - n_b * self.m3) / n;
+
         Self {
             min: self.min.min(rhs.min),
             max: self.max.max(rhs.max),
             count: combined_count,
             mean: new_mean,
             m2: new_m2,
+            m3: new_m3,
+            m4: new_m4,
         }
     }
 }
@@ -72,10 +111,47 @@ impl SegmentTree {
         }
     }
 
+    /// Builds a tree over `values` in O(N): the leaves are filled first, then every
+    /// parent is computed exactly once in a single bottom-up pass.
+    pub fn from_values(values: &[f64]) -> Self {
+        let capacity = values.len().max(1);
+        SegmentTree {
+            tree: Self::build(capacity, values),
+            capacity,
+        }
+    }
+
+    /// Lays out a tree of the given capacity over `values` bottom-up, in O(N).
+    fn build(capacity: usize, values: &[f64]) -> Vec<Node> {
+        let mut tree = vec![Node::default(); 2 * capacity];
+
+        for (i, &value) in values.iter().enumerate() {
+            tree[capacity + i] = Node {
+                min: value,
+                max: value,
+                count: 1,
+                mean: value,
+                m2: 0.0,
+                m3: 0.0,
+                m4: 0.0,
+            };
+        }
+        for i in (1..capacity).rev() {
+            tree[i] = tree[2 * i] + tree[2 * i + 1];
+        }
+
+        tree
+    }
+
     pub fn capacity(&self) -> usize {
         self.capacity
     }
 
+    /// The number of bytes allocated for the tree's nodes.
+    pub fn memory_footprint(&self) -> usize {
+        self.tree.capacity() * std::mem::size_of::<Node>()
+    }
+
     pub fn update(&mut self, index: usize, value: f64, all_values: &[f64]) {
         if index >= self.capacity {
             self.resize(index + 1, all_values);
@@ -101,6 +177,8 @@ impl SegmentTree {
                 count: 1,
                 mean: value,
                 m2: 0.0,
+                m3: 0.0,
+                m4: 0.0,
             };
         }
 
@@ -114,7 +192,6 @@ impl SegmentTree {
             end /= 2;
             for i in start..=end {
                 self.tree[i] = self.tree[2 * i] + self.tree[2 * i + 1];
-                
             }
         }
     }
@@ -128,6 +205,8 @@ impl SegmentTree {
             count: 1,
             mean: value,
             m2: 0.0,
+            m3: 0.0,
+            m4: 0.0,
         };
 
         while index > 1 {
@@ -136,22 +215,26 @@ impl SegmentTree {
         }
     }
 
-    /// Resizes the tree by creating a new, larger tree and rebuilding it.
-    /// This is an O(N * log N) operation.
+    /// Resizes the tree by creating a new, larger tree and rebuilding it
+    /// bottom-up from all existing values. This is an O(N) operation.
     fn resize(&mut self, required_capacity: usize, all_values: &[f64]) {
         let new_capacity = (self.capacity * 2).max(required_capacity);
+        self.grow(new_capacity, all_values);
+    }
+
+    /// Grows the tree to exactly `new_capacity` leaves, rebuilding it bottom-up
+    /// from `all_values` in O(N). Does nothing if the tree is already that large.
+    pub fn grow(&mut self, new_capacity: usize, all_values: &[f64]) {
+        if new_capacity <= self.capacity {
+            return;
+        }
         info!(
             old_capacity = self.capacity,
             new_capacity, "Resizing SegmentTree"
         );
 
         self.capacity = new_capacity;
-        self.tree = vec![Node::default(); 2 * new_capacity];
-
-        // Rebuild the tree with all existing values.
-        for (i, &v) in all_values.iter().enumerate() {
-            self.update_internal(i, v);
-        }
+        self.tree = Self::build(new_capacity, all_values);
     }
 
     /// Queries the tree for an aggregate Node over the given range [left, right].
@@ -180,7 +263,7 @@ impl SegmentTree {
                 left += 1;
             }
             // If right is a left child, include its value and move to the left.
-            if right % 2 == 0 {
+            if right.is_multiple_of(2) {
                 trace!(index = right, "Including left child in right result");
                 res_right = self.tree[right] + res_right;
                 right -= 1;
@@ -218,6 +301,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::vec_init_then_push)]
     fn test_single_element() {
         let mut tree = SegmentTree::new(10);
         let mut values = Vec::new();
@@ -339,4 +423,94 @@ mod tests {
         // Welford's algorithm passes.
         assert_float_eq(variance, 1.0);
     }
+
+    #[test]
+    fn test_from_values_matches_incremental_updates() {
+        let test_data: Vec<f64> = (0..37).map(|i| ((i * 13) % 17) as f64 + 0.5).collect();
+
+        let mut incremental = SegmentTree::new(test_data.len());
+        let mut values = Vec::new();
+        for (i, &v) in test_data.iter().enumerate() {
+            values.push(v);
+            incremental.update(i, v, &values);
+        }
+        let bulk = SegmentTree::from_values(&test_data);
+
+        for (left, right) in [(0, 36), (0, 0), (5, 20), (17, 36), (36, 36)] {
+            let expected = incremental.query(left, right);
+            let actual = bulk.query(left, right);
+            assert_eq!(actual.count, expected.count);
+            assert_float_eq(actual.min, expected.min);
+            assert_float_eq(actual.max, expected.max);
+            assert_float_eq(actual.mean, expected.mean);
+            assert_float_eq(actual.m2, expected.m2);
+            assert_float_eq(actual.m3, expected.m3);
+            assert_float_eq(actual.m4, expected.m4);
+        }
+    }
+
+    #[test]
+    fn test_higher_moments_full_range() {
+        let mut tree = SegmentTree::new(8);
+        let mut values = Vec::new();
+        let test_data = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+
+        for (i, &v) in test_data.iter().enumerate() {
+            values.push(v);
+            tree.update(i, v, &values);
+        }
+
+        let node = tree.query(0, 7);
+
+        // The mean is 5, so the deviations are [-3, -1, -1, -1, 0, 0, 2, 4].
+        // m3 = -27 - 1 - 1 - 1 + 8 + 64 = 42
+        // m4 = 81 + 1 + 1 + 1 + 16 + 256 = 356
+        assert_float_eq(node.m2, 32.0);
+        assert_float_eq(node.m3, 42.0);
+        assert_float_eq(node.m4, 356.0);
+
+        // skewness = sqrt(8) * 42 / 32^1.5 = 42 / 64
+        assert_float_eq(node.skewness(), 0.65625);
+        // excess kurtosis = 8 * 356 / 32^2 - 3
+        assert_float_eq(node.excess_kurtosis(), -0.21875);
+    }
+
+    #[test]
+    fn test_higher_moments_of_constant_values() {
+        let values = vec![7.0; 5];
+        let node = SegmentTree::from_values(&values).query(0, 4);
+
+        // The shape of a distribution without spread is undefined; it is reported as 0.
+        assert_float_eq(node.skewness(), 0.0);
+        assert_float_eq(node.excess_kurtosis(), 0.0);
+    }
+
+    #[test]
+    fn test_higher_moments_stability_with_large_offset() {
+        let mut tree = SegmentTree::new(4);
+        let mut values = Vec::new();
+
+        // The same large offset as the variance test, with an asymmetric spread.
+        const OFFSET: f64 = 1_000_000_000.0;
+        let test_data = [OFFSET, OFFSET, OFFSET, OFFSET + 4.0];
+
+        for (i, &v) in test_data.iter().enumerate() {
+            values.push(v);
+            tree.update(i, v, &values);
+        }
+
+        let node = tree.query(0, 3);
+
+        // The deviations from the mean (OFFSET + 1) are [-1, -1, -1, 3], so
+        // m2 = 12, m3 = 24 and m4 = 84. Raw power sums such as sum(x^4) would be
+        // around 4e36, where an f64 cannot even represent the difference.
+        assert_float_eq(node.m2, 12.0);
+        assert_float_eq(node.m3, 24.0);
+        assert_float_eq(node.m4, 84.0);
+
+        // skewness = 2 * 24 / 12^1.5 = 2 / sqrt(3)
+        assert_float_eq(node.skewness(), 2.0 / 3.0_f64.sqrt());
+        // excess kurtosis = 4 * 84 / 144 - 3 = -2 / 3
+        assert_float_eq(node.excess_kurtosis(), -2.0 / 3.0);
+    }
 }
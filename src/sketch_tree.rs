@@ -0,0 +1,356 @@
+use crate::quantile_tree::{chunk_size, from_key, to_key, FANOUT_BITS};
+use std::mem;
+
+/// The number of keys kept per sketch.
+const SKETCH_SIZE: usize = 256;
+/// The lowest level that carries sketches. Its chunks hold `16 * SKETCH_SIZE`
+/// values, so that every level compresses 16 sorted inputs per sketch key.
+const FIRST_SKETCH_LEVEL: usize = 3;
+/// The finest rank error that can be asked for, as a fraction of the window.
+/// Anything finer would mean scanning raw values over the whole window.
+pub const MIN_ACCURACY: f64 = 1.0 / SKETCH_SIZE as f64;
+
+/// A tree of mergeable quantile sketches answering approximate quantile queries
+/// with a deterministic rank-error bound, over the same slots as a `SegmentTree`.
+///
+/// The slots are split into the same aligned chunks of `16^j` as a
+/// `QuantileTree`, but every chunk of level 3 (4096 slots) or above keeps only an
+/// equi-depth sketch of 256 keys: the values of rank `16, 32, ...` within the
+/// chunk, each standing for `16^j / 256` values. A level-3 sketch samples its
+/// sorted values, which misranks any value by less than 16. A higher sketch
+/// keeps every 16th key of its 16 children's sketches merged, adding one more
+/// key's weight of error, so a level-`j` sketch misranks by at most
+/// `(j - 2) / 256` of its chunk. Chunks below level 3 are read from the raw values.
+///
+/// Sketches cost about half a byte per point. As with a `QuantileTree`, a chunk's
+/// sketch is rebuilt when its last slot is written.
+#[derive(Default)]
+pub struct SketchTree {
+    /// `levels[i]` holds the sketches of level `FIRST_SKETCH_LEVEL + i`, one run
+    /// of `SKETCH_SIZE` sorted keys per chunk.
+    levels: Vec<Vec<u64>>,
+}
+
+/// Approximate quantiles, and a bound on how far their ranks may be off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApproxQuantiles {
+    /// The values, in the order the quantiles were requested.
+    pub values: Vec<f64>,
+    /// The largest possible difference between each value's rank and the
+    /// requested one, as a fraction of the number of points.
+    pub rank_error: f64,
+}
+
+impl SketchTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a sketch for every complete chunk of `values`.
+    pub fn from_values(values: &[f64]) -> Self {
+        let mut tree = Self::new();
+        tree.update(0, values.len(), values);
+        tree
+    }
+
+    /// The highest level whose sketches are within `accuracy`, which must be
+    /// at least `MIN_ACCURACY`.
+    pub fn level_for_accuracy(accuracy: f64) -> usize {
+        let level = (accuracy * SKETCH_SIZE as f64).floor() as usize + FIRST_SKETCH_LEVEL - 1;
+        level.clamp(FIRST_SKETCH_LEVEL, 63 / FANOUT_BITS as usize)
+    }
+
+    /// Rebuilds every sketch whose chunk ends within the `count` slots starting
+    /// at `start`, which `all_values` must already contain.
+    pub fn update(&mut self, start: usize, count: usize, all_values: &[f64]) {
+        let end = start + count;
+        for index in 0..self.levels.len() {
+            let size = chunk_size(FIRST_SKETCH_LEVEL + index);
+            self.levels[index].resize(all_values.len() / size * SKETCH_SIZE, 0);
+
+            // Lower levels are rebuilt first, so each sketch merges fresh ones.
+            let mut chunk_start = start / size * size;
+            while chunk_start + size <= end {
+                self.build_sketch(index, chunk_start / size, all_values);
+                chunk_start += size;
+            }
+        }
+
+        // Add levels whose chunks now fit in the slots.
+        while chunk_size(FIRST_SKETCH_LEVEL + self.levels.len()) <= all_values.len() {
+            let index = self.levels.len();
+            let size = chunk_size(FIRST_SKETCH_LEVEL + index);
+            self.levels
+                .push(vec![0; all_values.len() / size * SKETCH_SIZE]);
+            for chunk in 0..all_values.len() / size {
+                self.build_sketch(index, chunk, all_values);
+            }
+        }
+    }
+
+    /// Builds the sketch of chunk number `chunk` of `levels[index]` from the
+    /// 16 * `SKETCH_SIZE` equally weighted keys below it, keeping every 16th.
+    fn build_sketch(&mut self, index: usize, chunk: usize, all_values: &[f64]) {
+        let inputs = SKETCH_SIZE << FANOUT_BITS;
+        let merged: Vec<u64> = if index == 0 {
+            let mut keys: Vec<u64> = all_values[chunk * inputs..(chunk + 1) * inputs]
+                .iter()
+                .map(|&value| to_key(value))
+                .collect();
+            keys.sort_unstable();
+            keys
+        } else {
+            let mut keys = self.levels[index - 1][chunk * inputs..(chunk + 1) * inputs].to_vec();
+            // 16 sorted runs, which a stable sort merges in O(n log 16).
+            keys.sort();
+            keys
+        };
+
+        let step = 1 << FANOUT_BITS;
+        let sketch = &mut self.levels[index][chunk * SKETCH_SIZE..(chunk + 1) * SKETCH_SIZE];
+        for (rank, key) in sketch.iter_mut().enumerate() {
+            *key = merged[(rank + 1) * step - 1];
+        }
+    }
+
+    /// The number of bytes allocated for sketches.
+    pub fn memory_footprint(&self) -> usize {
+        self.levels
+            .iter()
+            .map(|level| level.capacity() * mem::size_of::<u64>())
+            .sum()
+    }
+
+    /// Estimates the quantiles `qs` (each within `[0, 1]`) of the values in the
+    /// slot ranges `ranges` (each `[left, right]`, inclusive), using sketches up
+    /// to `max_level` (see `level_for_accuracy`). Each value is the nearest-rank
+    /// quantile of the estimated distribution.
+    pub fn quantiles(
+        &self,
+        ranges: &[(usize, usize)],
+        qs: &[f64],
+        max_level: usize,
+        all_values: &[f64],
+    ) -> ApproxQuantiles {
+        let mut items = Vec::new();
+        let mut error = 0;
+        for &(left, right) in ranges {
+            self.collect(left, right, max_level, all_values, &mut items, &mut error);
+        }
+        items.sort_unstable();
+
+        let cumulative: Vec<usize> = items
+            .iter()
+            .scan(0, |seen, &(_, weight)| {
+                *seen += weight;
+                Some(*seen)
+            })
+            .collect();
+        let total = cumulative.last().copied().unwrap_or(0);
+        let values = qs
+            .iter()
+            .map(|&q| {
+                let rank = ((q * total as f64).ceil() as usize).max(1);
+                let index = cumulative.partition_point(|&seen| seen < rank);
+                items.get(index).map_or(f64::NAN, |&(key, _)| from_key(key))
+            })
+            .collect();
+
+        ApproxQuantiles {
+            values,
+            rank_error: error as f64 / total.max(1) as f64,
+        }
+    }
+
+    /// Adds weighted keys standing for the slots `[left, right]` to `items`, and
+    /// their worst-case rank error to `error`.
+    fn collect(
+        &self,
+        left: usize,
+        right: usize,
+        max_level: usize,
+        all_values: &[f64],
+        items: &mut Vec<(u64, usize)>,
+        error: &mut usize,
+    ) {
+        let top = max_level.min(FIRST_SKETCH_LEVEL + self.levels.len() - 1);
+        let (mut low, mut high) = (left, right + 1);
+
+        // Climb the levels as a `QuantileTree` does, consuming whole chunks from
+        // both ends. The top level consumes the rest.
+        for level in 0..=top {
+            let size = chunk_size(level);
+            let next = size << FANOUT_BITS;
+            let is_top = level == top;
+
+            while low < high && (is_top || low % next != 0) && low + size <= high {
+                self.collect_chunk(level, low, all_values, items, error);
+                low += size;
+            }
+            while low < high && (is_top || high % next != 0) && high - size >= low {
+                self.collect_chunk(level, high - size, all_values, items, error);
+                high -= size;
+            }
+            if low >= high {
+                break;
+            }
+        }
+    }
+
+    fn collect_chunk(
+        &self,
+        level: usize,
+        chunk_start: usize,
+        all_values: &[f64],
+        items: &mut Vec<(u64, usize)>,
+        error: &mut usize,
+    ) {
+        let size = chunk_size(level);
+        if level < FIRST_SKETCH_LEVEL {
+            items.extend(
+                all_values[chunk_start..chunk_start + size]
+                    .iter()
+                    .map(|&value| (to_key(value), 1)),
+            );
+            return;
+        }
+
+        let weight = size / SKETCH_SIZE;
+        let chunk = chunk_start / size;
+        items.extend(
+            self.levels[level - FIRST_SKETCH_LEVEL][chunk * SKETCH_SIZE..(chunk + 1) * SKETCH_SIZE]
+                .iter()
+                .map(|&key| (key, weight)),
+        );
+        *error += (level - FIRST_SKETCH_LEVEL + 1) * weight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Checks that `value` could be the `q` quantile of `window` to within
+    /// `rank_error`: some rank it could occupy is close enough to the target.
+    fn assert_rank_within(window: &[f64], q: f64, value: f64, rank_error: f64) {
+        let n = window.len() as f64;
+        let below = window.iter().filter(|&&v| v < value).count() as f64;
+        let at_or_below = window.iter().filter(|&&v| v <= value).count() as f64;
+        let target = (q * n).ceil().max(1.0);
+        let slack = rank_error * n + 1e-9;
+        assert!(
+            below + 1.0 <= target + slack && at_or_below >= target - slack,
+            "q={} value={} occupies ranks {}..={} of {}, target {} +- {}",
+            q,
+            value,
+            below + 1.0,
+            at_or_below,
+            n,
+            target,
+            slack
+        );
+        assert!(window.contains(&value));
+    }
+
+    #[test]
+    fn test_level_for_accuracy() {
+        assert_eq!(SketchTree::level_for_accuracy(MIN_ACCURACY), 3);
+        assert_eq!(SketchTree::level_for_accuracy(0.0078125), 4);
+        assert_eq!(SketchTree::level_for_accuracy(0.01), 4);
+        assert_eq!(SketchTree::level_for_accuracy(1.0), 15);
+    }
+
+    #[test]
+    fn test_sketch_error_is_reported_and_respected() {
+        // Arrange: 70k values, so levels 3 and 4 carry sketches.
+        let values: Vec<f64> = (0..70_000u64)
+            .map(|i| ((i * 2_654_435_761) % 100_003) as f64)
+            .collect();
+        let tree = SketchTree::from_values(&values);
+        let qs = [0.0, 0.01, 0.5, 0.95, 0.99, 1.0];
+
+        for accuracy in [MIN_ACCURACY, 0.01, 0.05] {
+            // Act
+            let result = tree.quantiles(
+                &[(0, values.len() - 1)],
+                &qs,
+                SketchTree::level_for_accuracy(accuracy),
+                &values,
+            );
+
+            // Assert
+            assert!(result.rank_error > 0.0 && result.rank_error <= accuracy);
+            for (&q, &value) in qs.iter().zip(&result.values) {
+                assert_rank_within(&values, q, value, result.rank_error);
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn prop_quantiles_are_within_their_error_bound(
+            values in prop::collection::vec(0.0f64..1000.0, 1..20_000),
+            window_fraction in 0.0f64..=1.0,
+            accuracy in MIN_ACCURACY..=0.1,
+            qs in prop::collection::vec(0.0f64..=1.0, 1..8),
+        ) {
+            let tree = SketchTree::from_values(&values);
+            let window = ((values.len() as f64 * window_fraction) as usize).max(1);
+            let start = values.len() - window;
+
+            let result = tree.quantiles(
+                &[(start, values.len() - 1)],
+                &qs,
+                SketchTree::level_for_accuracy(accuracy),
+                &values,
+            );
+
+            prop_assert!(result.rank_error <= accuracy);
+            for (&q, &value) in qs.iter().zip(&result.values) {
+                assert_rank_within(&values[start..], q, value, result.rank_error);
+            }
+        }
+
+        #[test]
+        fn prop_ring_buffer_quantiles_are_within_their_error_bound(
+            batches in prop::collection::vec(
+                prop::collection::vec(0.0f64..1000.0, 1..3000),
+                1..12,
+            ),
+            q in 0.0f64..=1.0,
+        ) {
+            // Arrange: Write 5000 slots in order, wrapping like a ring buffer.
+            let limit = 5000;
+            let mut slots = vec![0.0; limit];
+            let mut tree = SketchTree::new();
+            let mut next_index = 0;
+            for batch in &batches {
+                for &value in batch {
+                    slots[next_index % limit] = value;
+                    tree.update(next_index % limit, 1, &slots);
+                    next_index += 1;
+                }
+            }
+
+            // Act: The retained window, split where it wraps.
+            let retained = next_index.min(limit);
+            let head = (next_index - 1) % limit;
+            let ranges = if next_index > limit && head + 1 < limit {
+                vec![(head + 1, limit - 1), (0, head)]
+            } else {
+                vec![(head + 1 - retained, head)]
+            };
+            let window: Vec<f64> = ranges
+                .iter()
+                .flat_map(|&(left, right)| slots[left..=right].to_vec())
+                .collect();
+            let result = tree.quantiles(&ranges, &[q], 15, &slots);
+
+            // Assert
+            assert_rank_within(&window, q, result.values[0], result.rank_error);
+        }
+    }
+}
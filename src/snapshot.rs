@@ -0,0 +1,294 @@
+use crate::store::{Store, SymbolData};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::info;
+
+/// Identifies a snapshot file.
+const MAGIC: &[u8; 8] = b"HFTSNAP\0";
+/// The current on-disk format version. Bump this whenever the layout changes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// The length of the magic, version and symbol count.
+const HEADER_LEN: usize = MAGIC.len() + 4 + 8;
+
+// Snapshot layout (all integers little-endian):
+//
+//   magic: [u8; 8]   version: u32   symbol_count: u64
+//   for each symbol:
+//     name_len: u32   name: [u8; name_len]
+//     created_at: u64   updated_at: u64
+//     total_points: u64   retained: u64
+//     values: [f64; retained]   timestamps: [u64; retained]
+//   checksum: u32   (CRC32 of everything before it)
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a snapshot file")]
+    BadMagic,
+    #[error("Unsupported snapshot version {0} (expected {SNAPSHOT_VERSION})")]
+    UnsupportedVersion(u32),
+    #[error("Snapshot checksum mismatch")]
+    ChecksumMismatch,
+    #[error("Corrupt snapshot: {0}")]
+    Corrupt(String),
+}
+
+/// A short description of a written or loaded snapshot.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SnapshotSummary {
+    pub symbols: usize,
+    pub points: usize,
+}
+
+/// Writes every symbol in `store` to `path`.
+///
+/// Each symbol is written straight from its storage under its own read lock, so
+/// the snapshot never holds a second copy of the data, and only writers to the
+/// symbol being written (or others sharing its shard) wait. The file is written
+/// to a temporary path, synced and then renamed over `path`, so a crash never
+/// leaves a half-written snapshot.
+pub fn write(store: &Store, path: &Path) -> Result<SnapshotSummary, SnapshotError> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp_path = temporary_path(path);
+    let mut file = File::create(&tmp_path)?;
+    // The header ends with the symbol count, which is only known once every
+    // symbol has been written, so it is written last, over this placeholder.
+    file.write_all(&[0; HEADER_LEN])?;
+    let mut writer = ChecksumWriter::new(BufWriter::new(file));
+
+    // Collect the keys first; symbols created after this point are not included.
+    let symbols: Vec<String> = store.symbols.iter().map(|e| e.key().clone()).collect();
+
+    let mut summary = SnapshotSummary::default();
+    for symbol in symbols {
+        // A symbol may have been removed since the keys were collected.
+        let Some(data) = store.symbols.get(&symbol) else {
+            continue;
+        };
+        write_symbol(&mut writer, &symbol, &data)?;
+
+        summary.symbols += 1;
+        summary.points += data.retained_len();
+    }
+    writer.flush()?;
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    header.extend_from_slice(&(summary.symbols as u64).to_le_bytes());
+
+    // The checksum covers the header as if it had been written first.
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header);
+    hasher.combine(&writer.hasher);
+
+    let mut file = writer.inner.into_inner().map_err(|e| e.into_error())?;
+    file.write_all(&hasher.finalize().to_le_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)?;
+
+    info!(
+        path = %path.display(),
+        symbols = summary.symbols,
+        points = summary.points,
+        "Snapshot written"
+    );
+    Ok(summary)
+}
+
+/// Reads a snapshot from `path` and inserts every symbol into `store`, using
+/// `store`'s current retention settings. Nothing is inserted unless the whole
+/// file is valid.
+pub fn load(store: &Store, path: &Path) -> Result<SnapshotSummary, SnapshotError> {
+    let mut reader = ChecksumReader::new(BufReader::new(File::open(path)?));
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = read_u32(&mut reader)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let symbol_count = read_u64(&mut reader)?;
+    let mut entries = Vec::new();
+    for _ in 0..symbol_count {
+        // The checksum has not been verified yet, so don't trust the length
+        // enough to allocate it up front; a corrupt one just runs out of file.
+        let name_len = read_u32(&mut reader)? as usize;
+        let mut name = Vec::new();
+        reader
+            .by_ref()
+            .take(name_len as u64)
+            .read_to_end(&mut name)?;
+        if name.len() != name_len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let symbol = String::from_utf8(name)
+            .map_err(|_| SnapshotError::Corrupt("symbol name is not valid UTF-8".to_string()))?;
+
+        let created_at = read_u64(&mut reader)?;
+        let updated_at = read_u64(&mut reader)?;
+
+        let total_points = read_u64(&mut reader)? as usize;
+        let retained = read_u64(&mut reader)? as usize;
+        if retained > total_points {
+            return Err(SnapshotError::Corrupt(format!(
+                "symbol '{}' retains {} of {} points",
+                symbol, retained, total_points
+            )));
+        }
+
+        let values = (0..retained)
+            .map(|_| read_u64(&mut reader).map(f64::from_bits))
+            .collect::<Result<Vec<_>, _>>()?;
+        let timestamps = (0..retained)
+            .map(|_| read_u64(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        entries.push((
+            symbol,
+            created_at,
+            updated_at,
+            total_points,
+            values,
+            timestamps,
+        ));
+    }
+
+    let expected = reader.checksum();
+    let actual = read_u32(reader.get_mut())?;
+    if expected != actual {
+        return Err(SnapshotError::ChecksumMismatch);
+    }
+
+    let mut summary = SnapshotSummary::default();
+    for (symbol, created_at, updated_at, total_points, values, timestamps) in entries {
+        summary.symbols += 1;
+        summary.points += values.len();
+
+        let retention = store.retention().limit_for(&symbol);
+        let data = SymbolData::restore(
+            retention,
+            total_points,
+            values,
+            timestamps,
+            created_at,
+            updated_at,
+        );
+        store.symbols.insert(symbol, data);
+    }
+
+    info!(
+        path = %path.display(),
+        symbols = summary.symbols,
+        points = summary.points,
+        "Snapshot loaded"
+    );
+    Ok(summary)
+}
+
+/// Writes one symbol's entry, as laid out above.
+fn write_symbol(writer: &mut impl Write, symbol: &str, data: &SymbolData) -> io::Result<()> {
+    writer.write_all(&(symbol.len() as u32).to_le_bytes())?;
+    writer.write_all(symbol.as_bytes())?;
+    writer.write_all(&data.created_at().to_le_bytes())?;
+    writer.write_all(&data.updated_at().to_le_bytes())?;
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    writer.write_all(&(data.retained_len() as u64).to_le_bytes())?;
+    for value in data.retained_values() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    for timestamp in data.retained_timestamps() {
+        writer.write_all(&timestamp.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// A writer that keeps a running CRC32 of everything written through it.
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader that keeps a running CRC32 of everything read through it.
+struct ChecksumReader<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    fn checksum(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
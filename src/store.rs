@@ -1,94 +1,1458 @@
-use crate::{segment_tree::SegmentTree, AppError};
-use dashmap::DashMap;
+use crate::{
+    config::{LimitsConfig, RetentionConfig},
+    quantile_tree::QuantileTree,
+    segment_tree::{Node, SegmentTree},
+    sketch_tree::{ApproxQuantiles, SketchTree},
+    snapshot::{self, SnapshotSummary},
+    validation::{self, InvalidValuePolicy},
+    wal::{BatchRecord, Lsn, ReplaySummary, Wal, WalRecord},
+    AppError,
+};
+use dashmap::{
+    mapref::{entry::Entry, one::Ref},
+    DashMap,
+};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch};
+use tracing::warn;
 
-/// The initial capacity for the segment tree.
-const STARTING_CAPACITY: usize = 1_000_000;
-// The maximum number of unique symbols we can track.
-const MAX_SYMBOLS: usize = 10;
+/// How many change notifications a subscriber may fall behind by before the
+/// oldest are discarded and it is told that it lagged.
+const UPDATE_CHANNEL_CAPACITY: usize = 1024;
+
+/// Hands out `SymbolData::generation`.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Quantiles over a window of a symbol's history.
+#[derive(Debug, Clone)]
+pub struct QuantileStats {
+    /// The number of points in the window.
+    pub count: u64,
+    /// Each requested quantile with its value, in request order.
+    pub quantiles: Vec<(f64, f64)>,
+    /// For approximate quantiles, the largest possible difference between each
+    /// value's rank and the requested one, as a fraction of `count`.
+    pub rank_error: Option<f64>,
+}
+
+/// One symbol's values and timestamps within a multi-symbol ingestion.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolBatch<'a> {
+    pub symbol: &'a str,
+    pub values: &'a [f64],
+    /// Milliseconds since the Unix epoch, one per value, or `None` to have the
+    /// store stamp the values itself (see `Store::add_symbol_batch`).
+    pub timestamps: Option<&'a [u64]>,
+    pub policy: InvalidValuePolicy,
+    /// The number of points the symbol is expected to hold, if known, so that its
+    /// storage can be sized once instead of growing step by step.
+    pub capacity_hint: Option<usize>,
+}
+
+/// A batch that passed `Store::check_batches`, ready to be logged and applied.
+struct CheckedBatch<'a> {
+    symbol: &'a str,
+    /// The absolute index the batch's first value will have.
+    start_index: usize,
+    values: Cow<'a, [f64]>,
+    timestamps: Cow<'a, [u64]>,
+    dropped: Vec<usize>,
+    capacity_hint: Option<usize>,
+}
+
+/// A description of a symbol and the data it holds.
+#[derive(Debug, Clone)]
+pub struct SymbolInfo {
+    pub symbol: String,
+    /// The total number of points ever added.
+    pub points: usize,
+    /// The number of points still retained.
+    pub retained: usize,
+    /// The absolute index of the oldest retained point.
+    pub first_index: usize,
+    /// The number of points the symbol's storage can hold before it grows.
+    pub capacity: usize,
+    /// The maximum number of points retained, or `None` if unbounded.
+    pub retention: Option<usize>,
+    /// The oldest retained value, if any.
+    pub first_value: Option<f64>,
+    /// The most recent value, if any.
+    pub last_value: Option<f64>,
+    /// When the symbol was created, in milliseconds since the Unix epoch.
+    pub created_at: u64,
+    /// When data was last added, in milliseconds since the Unix epoch.
+    pub updated_at: u64,
+    /// The approximate number of bytes allocated for the symbol's data.
+    pub memory_bytes: usize,
+}
 
 /// The main store for all symbol data.
 pub struct Store {
     pub symbols: DashMap<String, SymbolData>,
+    limits: LimitsConfig,
+    retention: RetentionConfig,
+    snapshot_path: Option<PathBuf>,
+    // Serializes snapshot writers so they never race on the temporary file.
+    snapshot_lock: Mutex<()>,
+    // Lets one exact-quantile tree be built at a time, so that concurrent first
+    // queries don't each sort their own copy of a symbol's history.
+    quantile_build_lock: Mutex<()>,
+    wal: Option<Wal>,
+    // Held shared by single-symbol writes, removals and resets, and exclusively
+    // by `add_batches_atomic` and by snapshots while they rotate the log.
+    ingest_gate: RwLock<()>,
+    // Announces the name of every symbol whose data changes.
+    updates: broadcast::Sender<Arc<str>>,
+    // Set once push streams should end, so they don't hold up a graceful shutdown.
+    closing: watch::Sender<bool>,
+}
+
+/// A complete statistics object, decoupled from the web response.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolStats {
+    pub min: f64,
+    pub max: f64,
+    pub last: f64,
+    pub avg: f64,
+    /// The variance, dividing the sum of squared deviations by `count - ddof`.
+    pub var: f64,
+    /// The population skewness.
+    pub skewness: f64,
+    /// The population excess kurtosis: 0 for normally distributed values,
+    /// positive for fatter tails.
+    pub kurtosis: f64,
+    /// The number of points the statistics cover.
+    pub count: u64,
+    /// The sum of the points.
+    pub sum: f64,
+    /// The absolute index of the first point covered.
+    pub start_index: usize,
+    /// The absolute index of the last point covered.
+    pub end_index: usize,
+    /// Whether the requested window reached past the retained data, so fewer
+    /// points were covered than asked for.
+    pub truncated: bool,
+    /// The delta degrees of freedom of `var`: 0 for the population variance,
+    /// 1 for the unbiased sample variance.
+    pub ddof: u32,
+}
+
+impl SymbolStats {
+    /// Rescales `var` to divide by `count - ddof` instead of `count - self.ddof`.
+    /// Fails if the window does not hold more than `ddof` points.
+    pub fn with_ddof(self, ddof: u32) -> Result<Self, AppError> {
+        if self.count <= u64::from(ddof) {
+            return Err(AppError::NotEnoughData);
+        }
+        let m2 = self.var * (self.count - u64::from(self.ddof)) as f64;
+        Ok(Self {
+            var: m2 / (self.count - u64::from(ddof)) as f64,
+            ddof,
+            ..self
+        })
+    }
+
+    /// The standard deviation, the square root of `var`.
+    pub fn stddev(&self) -> f64 {
+        self.var.sqrt()
+    }
+}
+
+/// Statistics over an explicit, absolute index range of a symbol's history.
+#[derive(Debug, Clone, Copy)]
+pub struct RangeStats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub var: f64,
+    pub count: u64,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-/// A complete statistics object, decoupled from the web response.
-#[derive(Debug, Clone, Copy)]
-pub struct SymbolStats {
-    pub min: f64,
-    pub max: f64,
-    pub last: f64,
-    pub avg: f64,
-    pub var: f64,
-}
+impl Store {
+    pub fn new() -> Self {
+        Self {
+            symbols: DashMap::new(),
+            limits: LimitsConfig::default(),
+            retention: RetentionConfig::default(),
+            snapshot_path: None,
+            snapshot_lock: Mutex::new(()),
+            quantile_build_lock: Mutex::new(()),
+            wal: None,
+            ingest_gate: RwLock::new(()),
+            updates: broadcast::channel(UPDATE_CHANNEL_CAPACITY).0,
+            closing: watch::Sender::new(false),
+        }
+    }
+
+    /// Sets the service limits, such as the maximum number of symbols.
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets how many of the most recent points each new symbol retains.
+    pub fn with_retention(mut self, retention: RetentionConfig) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Sets the file that `save_snapshot` and `load_snapshot` use.
+    pub fn with_snapshot_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.snapshot_path = Some(path.into());
+        self
+    }
+
+    /// Logs every accepted batch to `wal` before applying it.
+    pub fn with_wal(mut self, wal: Wal) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Subscribes to the names of symbols whose data changes: one notification
+    /// after every batch is added, and after a symbol is reset or removed.
+    ///
+    /// Notifications never wait for subscribers. One that falls more than
+    /// `UPDATE_CHANNEL_CAPACITY` notifications behind misses the oldest and
+    /// receives `RecvError::Lagged` instead, after which it should assume that
+    /// every symbol changed.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<str>> {
+        self.updates.subscribe()
+    }
+
+    /// Asks every push stream to end. A graceful shutdown waits for open
+    /// connections to finish, which long-lived streams otherwise never do.
+    pub fn close_streams(&self) {
+        self.closing.send_replace(true);
+    }
+
+    /// Resolves once `close_streams` has been called, immediately if it already has.
+    pub fn streams_closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut closing = self.closing.subscribe();
+        async move {
+            // An error means the store was dropped, which closes the streams too.
+            let _ = closing.wait_for(|&closed| closed).await;
+        }
+    }
+
+    fn notify(&self, symbol: &str) {
+        // Skip the allocation when nobody is listening; a send error only
+        // means that the last subscriber has just gone away.
+        if self.updates.receiver_count() > 0 {
+            let _ = self.updates.send(Arc::from(symbol));
+        }
+    }
+
+    /// Grows the storage of an existing symbol to hold at least `capacity`
+    /// points (up to its retention limit), so that it does not have to grow
+    /// step by step as data arrives.
+    pub fn reserve(&self, symbol: &str, capacity: usize) -> Result<(), AppError> {
+        let mut data = self
+            .symbols
+            .get_mut(symbol)
+            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+        data.reserve(capacity);
+        Ok(())
+    }
+
+    /// The approximate number of bytes allocated for all symbols' data.
+    pub fn memory_footprint(&self) -> usize {
+        self.symbols
+            .iter()
+            .map(|entry| entry.memory_footprint())
+            .sum()
+    }
+
+    pub fn limits(&self) -> &LimitsConfig {
+        &self.limits
+    }
+
+    pub fn retention(&self) -> &RetentionConfig {
+        &self.retention
+    }
+
+    /// Writes every symbol to the configured snapshot file.
+    pub fn save_snapshot(&self) -> Result<SnapshotSummary, AppError> {
+        let path = self
+            .snapshot_path
+            .as_ref()
+            .ok_or_else(|| AppError::BadRequest("Snapshots are not enabled".to_string()))?;
+
+        let _guard = self
+            .snapshot_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        // Every write logs and applies its records while holding the ingest gate,
+        // so once the rotation has the gate to itself, every record in earlier
+        // segments is applied and the snapshot includes it. Once the snapshot is
+        // on disk they can be dropped.
+        let segment = self
+            .wal
+            .as_ref()
+            .map(|wal| {
+                let _gate = self
+                    .ingest_gate
+                    .write()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                wal.rotate()
+            })
+            .transpose()?;
+        let summary = snapshot::write(self, path)?;
+        if let (Some(wal), Some(segment)) = (&self.wal, segment) {
+            wal.remove_segments_before(segment)?;
+        }
+        Ok(summary)
+    }
+
+    /// Re-applies every batch in the write-ahead log. Run this after
+    /// `load_snapshot`; batches the snapshot already contains are skipped.
+    pub fn replay_wal(&self) -> Result<Option<ReplaySummary>, AppError> {
+        let Some(wal) = &self.wal else {
+            return Ok(None);
+        };
+        Ok(Some(wal.replay(|record| self.apply_replayed(record))?))
+    }
+
+    /// Flushes the write-ahead log to stable storage, if there is one.
+    pub fn sync_wal(&self) -> Result<(), AppError> {
+        if let Some(wal) = &self.wal {
+            wal.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs a logged record as the fsync policy requires. Writers call this
+    /// once they have released their locks, so the disk round trip holds up
+    /// only their own request.
+    fn commit_wal(&self, lsn: Option<Lsn>) -> Result<(), AppError> {
+        if let (Some(wal), Some(lsn)) = (&self.wal, lsn) {
+            wal.commit(lsn)?;
+        }
+        Ok(())
+    }
+
+    /// Creates empty data for `symbol`, with room for `capacity_hint` points if
+    /// that is more than the configured starting capacity.
+    fn new_symbol_data(&self, symbol: &str, capacity_hint: Option<usize>) -> SymbolData {
+        SymbolData::new(
+            self.retention.limit_for(symbol),
+            capacity_hint.map_or(self.limits.starting_capacity, |hint| {
+                hint.max(self.limits.starting_capacity)
+            }),
+        )
+    }
+
+    /// Applies a replayed record. Removals and resets are replayed as they
+    /// happened, so the store converges on the logged state even if the snapshot
+    /// it was loaded from already included some later records.
+    fn apply_replayed(&self, record: WalRecord) {
+        match record {
+            WalRecord::Batch(batch) => self.apply_replayed_batch(batch),
+            WalRecord::Group(batches) => {
+                for batch in batches {
+                    self.apply_replayed_batch(batch);
+                }
+            }
+            WalRecord::Remove { symbol } => {
+                self.symbols.remove(&symbol);
+            }
+            WalRecord::Reset { symbol } => {
+                let data = self.new_symbol_data(&symbol, None);
+                self.symbols.insert(symbol, data);
+            }
+        }
+    }
+
+    /// Applies a replayed batch. Records carry the absolute index of their first
+    /// value, so any part the store already holds is skipped.
+    fn apply_replayed_batch(&self, record: BatchRecord) {
+        let mut data = self
+            .symbols
+            .entry(record.symbol.clone())
+            .or_insert_with(|| self.new_symbol_data(&record.symbol, None));
+
+        let start_index = record.start_index as usize;
+        if start_index > data.len() {
+            warn!(
+                symbol = %record.symbol,
+                expected = data.len(),
+                found = start_index,
+                "Gap in write-ahead log; appending batch at the current end"
+            );
+        }
+
+        let already_applied = data.len().saturating_sub(start_index);
+        if already_applied < record.values.len() {
+            data.append(
+                &record.values[already_applied..],
+                &record.timestamps[already_applied..],
+            );
+        }
+    }
+
+    /// Restores symbols from the configured snapshot file. Returns `None` if
+    /// snapshots are disabled or no snapshot has been written yet.
+    pub fn load_snapshot(&self) -> Result<Option<SnapshotSummary>, AppError> {
+        match &self.snapshot_path {
+            Some(path) if path.exists() => Ok(Some(snapshot::load(self, path)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Adds a batch of values for a given symbol, stamping every value with the
+    /// current wall-clock time (see `add_symbol_batch`).
+    pub fn add_batch(&self, symbol: &str, batch_values: &[f64]) -> Result<(), AppError> {
+        self.add_symbol_batch(&SymbolBatch {
+            symbol,
+            values: batch_values,
+            timestamps: None,
+            policy: InvalidValuePolicy::Reject,
+            capacity_hint: None,
+        })
+        .map(|_| ())
+    }
+
+    /// Adds a batch of values with their timestamps (milliseconds since the Unix epoch).
+    /// The batch must pass `validation::validate_batch`, and is rejected whole if
+    /// any value is invalid. Timestamps must be non-decreasing, both within the
+    /// batch and relative to the last value already stored for the symbol.
+    pub fn add_batch_with_timestamps(
+        &self,
+        symbol: &str,
+        batch_values: &[f64],
+        batch_timestamps: &[u64],
+    ) -> Result<(), AppError> {
+        self.add_batch_with_policy(
+            symbol,
+            batch_values,
+            batch_timestamps,
+            InvalidValuePolicy::Reject,
+        )
+        .map(|_| ())
+    }
+
+    /// Adds a batch like `add_batch_with_timestamps`, handling invalid values
+    /// according to `policy`. Returns the indices of the values that were dropped;
+    /// if every value was dropped, nothing is added.
+    pub fn add_batch_with_policy(
+        &self,
+        symbol: &str,
+        batch_values: &[f64],
+        batch_timestamps: &[u64],
+        policy: InvalidValuePolicy,
+    ) -> Result<Vec<usize>, AppError> {
+        self.add_symbol_batch(&SymbolBatch {
+            symbol,
+            values: batch_values,
+            timestamps: Some(batch_timestamps),
+            policy,
+            capacity_hint: None,
+        })
+    }
+
+    /// Adds a batch like `add_batch_with_policy`. A batch without timestamps is
+    /// stamped with the current time, or with the symbol's last timestamp if the
+    /// clock is behind it, so it is never rejected for going back in time.
+    ///
+    /// The batch is synced to the write-ahead log after it is applied. If that
+    /// fails, the batch stays applied but an error is returned, since it may not
+    /// survive a crash.
+    pub fn add_symbol_batch(&self, batch: &SymbolBatch) -> Result<Vec<usize>, AppError> {
+        let symbol = batch.symbol;
+        let valid =
+            validation::validate_batch(batch.values, batch.timestamps, &self.limits, batch.policy)?;
+        if valid.values.is_empty() {
+            return Ok(valid.dropped);
+        }
+        if let Some(timestamps) = &valid.timestamps {
+            check_batch_timestamps(timestamps)?;
+        }
+
+        let gate = self.shared_gate();
+
+        // If the symbol doesn't exist yet and we are at capacity, reject the request.
+        if !self.symbols.contains_key(symbol) && self.symbols.len() >= self.limits.max_symbols {
+            return Err(AppError::BadRequest(format!(
+                "Maximum number of unique symbols ({}) reached.",
+                self.limits.max_symbols
+            )));
+        }
+
+        let (mut symbol_data_guard, created) = match self.symbols.entry(symbol.to_string()) {
+            Entry::Occupied(entry) => (entry.into_ref(), false),
+            Entry::Vacant(entry) => {
+                let data = self.new_symbol_data(symbol, batch.capacity_hint);
+                (entry.insert(data), true)
+            }
+        };
+
+        // A symbol this batch created has no timestamps yet, so this can only
+        // fail for an existing one.
+        let batch_timestamps = continue_timestamps(
+            valid.timestamps,
+            valid.values.len(),
+            symbol_data_guard.last_timestamp(),
+            now_millis(),
+        )?;
+        let batch_values = &*valid.values;
+
+        // Log the batch while holding the symbol's lock, so the log order matches
+        // the order in which batches are applied.
+        let logged = self.wal.as_ref().map(|wal| {
+            let start_index = symbol_data_guard.len();
+            wal.append_batch(symbol, start_index, batch_values, &batch_timestamps)
+        });
+        let lsn = match logged.transpose() {
+            Ok(lsn) => lsn,
+            Err(e) => {
+                // Don't leave behind a symbol that this batch created. An existing
+                // symbol stays, even if it is empty because it was just reset.
+                drop(symbol_data_guard);
+                if created {
+                    self.symbols.remove_if(symbol, |_, data| data.is_empty());
+                }
+                return Err(e.into());
+            }
+        };
+
+        // Size an existing symbol's storage before appending, so it grows once.
+        if let Some(capacity_hint) = batch.capacity_hint {
+            symbol_data_guard.reserve(capacity_hint);
+        }
+        symbol_data_guard.append(batch_values, &batch_timestamps);
+        drop(symbol_data_guard);
+        drop(gate);
+        self.notify(symbol);
+
+        self.commit_wal(lsn)?;
+        Ok(valid.dropped)
+    }
+
+    /// Adds every one of `batches` in order, or none of them if any would be
+    /// rejected by `add_symbol_batch`. Returns the indices of the values dropped
+    /// from each batch.
+    ///
+    /// Other writes wait while the batches are checked, logged and applied, so
+    /// none of them can invalidate a batch that has already been checked. All
+    /// batches are logged with a single write before any is applied, so a failed
+    /// write applies none of them; like `add_symbol_batch`, they are synced once
+    /// applied. Readers don't wait, and may see some of the batches before the
+    /// rest are applied.
+    pub fn add_batches_atomic(&self, batches: &[SymbolBatch]) -> Result<Vec<Vec<usize>>, AppError> {
+        let gate = self
+            .ingest_gate
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let checked = self.check_batches(batches)?;
+        let applied = || checked.iter().filter(|batch| !batch.values.is_empty());
+
+        let lsn = self
+            .wal
+            .as_ref()
+            .map(|wal| {
+                wal.append_batches(applied().map(|batch| {
+                    (
+                        batch.symbol,
+                        batch.start_index,
+                        &*batch.values,
+                        &*batch.timestamps,
+                    )
+                }))
+            })
+            .transpose()?;
+
+        for batch in applied() {
+            let mut data = self
+                .symbols
+                .entry(batch.symbol.to_string())
+                .or_insert_with(|| self.new_symbol_data(batch.symbol, batch.capacity_hint));
+            if let Some(capacity_hint) = batch.capacity_hint {
+                data.reserve(capacity_hint);
+            }
+            data.append(&batch.values, &batch.timestamps);
+            drop(data);
+            self.notify(batch.symbol);
+        }
+        drop(gate);
+
+        self.commit_wal(lsn)?;
+        Ok(checked.into_iter().map(|batch| batch.dropped).collect())
+    }
+
+    /// Checks `batches` as `add_symbol_batch` would if they were all added in
+    /// order, without adding any of them. Symbols created by earlier batches
+    /// count towards the symbol limit, and a symbol's batches must continue
+    /// each other's timestamps. Fails with the first batch that would be rejected.
+    fn check_batches<'a>(
+        &self,
+        batches: &[SymbolBatch<'a>],
+    ) -> Result<Vec<CheckedBatch<'a>>, AppError> {
+        // The last timestamp and length of each symbol, as if the earlier
+        // batches had been applied.
+        let mut symbols: HashMap<&str, (Option<u64>, usize)> = HashMap::new();
+        let mut new_symbols: HashSet<&str> = HashSet::new();
+        let now = now_millis();
+
+        batches
+            .iter()
+            .enumerate()
+            .map(|(index, batch)| {
+                self.check_batch(batch, now, &mut symbols, &mut new_symbols)
+                    .map_err(|source| AppError::BatchRejected {
+                        index,
+                        symbol: batch.symbol.to_string(),
+                        source: Box::new(source),
+                    })
+            })
+            .collect()
+    }
+
+    fn check_batch<'a>(
+        &self,
+        batch: &SymbolBatch<'a>,
+        now: u64,
+        symbols: &mut HashMap<&'a str, (Option<u64>, usize)>,
+        new_symbols: &mut HashSet<&'a str>,
+    ) -> Result<CheckedBatch<'a>, AppError> {
+        let valid =
+            validation::validate_batch(batch.values, batch.timestamps, &self.limits, batch.policy)?;
+        let (last, len) = *symbols.entry(batch.symbol).or_insert_with(|| {
+            self.symbols
+                .get(batch.symbol)
+                .map_or((None, 0), |data| (data.last_timestamp(), data.len()))
+        });
+        let mut checked = CheckedBatch {
+            symbol: batch.symbol,
+            start_index: len,
+            timestamps: Cow::Borrowed(&[]),
+            values: valid.values,
+            dropped: valid.dropped,
+            capacity_hint: batch.capacity_hint,
+        };
+        if checked.values.is_empty() {
+            return Ok(checked);
+        }
+        if let Some(timestamps) = &valid.timestamps {
+            check_batch_timestamps(timestamps)?;
+        }
+
+        if !self.symbols.contains_key(batch.symbol) && !new_symbols.contains(batch.symbol) {
+            if self.symbols.len() + new_symbols.len() >= self.limits.max_symbols {
+                return Err(AppError::BadRequest(format!(
+                    "Maximum number of unique symbols ({}) reached.",
+                    self.limits.max_symbols
+                )));
+            }
+            new_symbols.insert(batch.symbol);
+        }
+
+        checked.timestamps =
+            continue_timestamps(valid.timestamps, checked.values.len(), last, now)?;
+        symbols.insert(
+            batch.symbol,
+            (
+                checked.timestamps.last().copied(),
+                len + checked.values.len(),
+            ),
+        );
+        Ok(checked)
+    }
+
+    /// Holds off `add_batches_atomic` while a single-symbol write, removal or
+    /// reset is in progress.
+    fn shared_gate(&self) -> RwLockReadGuard<'_, ()> {
+        self.ingest_gate
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Removes a symbol and all of its data, freeing its slot towards the
+    /// symbol limit.
+    ///
+    /// The removal is logged and applied under the symbol's entry lock, so a
+    /// concurrent `add_batch` for the same symbol is ordered entirely before it
+    /// or entirely after it (re-creating the symbol).
+    pub fn remove_symbol(&self, symbol: &str) -> Result<(), AppError> {
+        let gate = self.shared_gate();
+        let Entry::Occupied(entry) = self.symbols.entry(symbol.to_string()) else {
+            return Err(AppError::SymbolNotFound(symbol.to_string()));
+        };
+        let lsn = self
+            .wal
+            .as_ref()
+            .map(|wal| wal.append_remove(symbol))
+            .transpose()?;
+        entry.remove();
+        drop(gate);
+        self.notify(symbol);
+
+        self.commit_wal(lsn)
+    }
+
+    /// Discards all of a symbol's data but keeps the symbol, so that it starts
+    /// again from index 0 with no timestamp history.
+    pub fn reset_symbol(&self, symbol: &str) -> Result<(), AppError> {
+        let gate = self.shared_gate();
+        let mut data = self
+            .symbols
+            .get_mut(symbol)
+            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+        let lsn = self
+            .wal
+            .as_ref()
+            .map(|wal| wal.append_reset(symbol))
+            .transpose()?;
+        let created_at = data.created_at;
+        *data = self.new_symbol_data(symbol, None);
+        data.created_at = created_at;
+        drop(data);
+        drop(gate);
+        self.notify(symbol);
+
+        self.commit_wal(lsn)
+    }
+
+    /// Describes a single symbol.
+    pub fn symbol_info(&self, symbol: &str) -> Result<SymbolInfo, AppError> {
+        let data = self
+            .symbols
+            .get(symbol)
+            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+        Ok(data.info(symbol))
+    }
+
+    /// Lists the symbols starting with `prefix` in name order, skipping the first
+    /// `offset` and returning at most `limit`. Also returns the total number of
+    /// matching symbols, so callers can page through them.
+    pub fn list_symbols(
+        &self,
+        prefix: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (Vec<SymbolInfo>, usize) {
+        let mut names: Vec<String> = self
+            .symbols
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix))
+            .map(|entry| entry.key().clone())
+            .collect();
+        names.sort_unstable();
+
+        let total = names.len();
+        let page = names
+            .iter()
+            .skip(offset)
+            .take(limit)
+            // A symbol may have been removed since the names were collected.
+            .filter_map(|name| self.symbols.get(name).map(|data| data.info(name)))
+            .collect();
+        (page, total)
+    }
+
+    /// Retrieves and calculates full statistics for a given symbol and window.
+    /// This now contains the final avg/var calculations.
+    pub fn get_stats(&self, symbol: &str, window_size: usize) -> Result<SymbolStats, AppError> {
+        let data = self
+            .symbols
+            .get(symbol)
+            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+
+        data.window_stats(window_size)
+    }
+
+    /// Retrieves statistics for several trailing windows of a symbol at once, in
+    /// the order given. All windows are computed under a single read lock, so they
+    /// reflect the same version of the data even while batches are being added.
+    pub fn get_stats_for_windows(
+        &self,
+        symbol: &str,
+        window_sizes: &[usize],
+    ) -> Result<Vec<SymbolStats>, AppError> {
+        let data = self
+            .symbols
+            .get(symbol)
+            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+
+        window_sizes
+            .iter()
+            .map(|&window_size| data.window_stats(window_size))
+            .collect()
+    }
+
+    /// Computes exact quantiles over the last `window_size` points of a symbol.
+    /// Each `q` must be within `[0, 1]`; values between two points are linearly
+    /// interpolated, so `0.5` of an even-sized window is the mean of the middle two.
+    ///
+    /// The first such query for a symbol builds its merge-sort tree (see
+    /// `quantile_data`), which is then kept up to date for as long as the symbol
+    /// exists.
+    pub fn get_quantiles(
+        &self,
+        symbol: &str,
+        window_size: usize,
+        qs: &[f64],
+    ) -> Result<QuantileStats, AppError> {
+        let data = self.quantile_data(symbol)?;
+
+        let actual_window_size = window_size.min(data.retained_len());
+        if actual_window_size == 0 {
+            return Err(AppError::NotEnoughData);
+        }
+        let end = data.len() - 1;
+        let start = data.len() - actual_window_size;
+
+        let quantiles = qs
+            .iter()
+            .map(|&q| {
+                let position = q * (actual_window_size - 1) as f64;
+                let lower_rank = position.floor() as usize;
+                let lower = data.select(start, end, lower_rank);
+                let fraction = position - lower_rank as f64;
+                let value = if fraction > 0.0 {
+                    let upper = data.select(start, end, lower_rank + 1);
+                    lower + fraction * (upper - lower)
+                } else {
+                    lower
+                };
+                (q, value)
+            })
+            .collect();
+
+        Ok(QuantileStats {
+            count: actual_window_size as u64,
+            quantiles,
+            rank_error: None,
+        })
+    }
+
+    /// Returns a symbol's data with its exact-quantile tree built. Building it
+    /// sorts a copy of the history without holding the symbol's lock; writers
+    /// only wait while the tree catches up with the points they appended
+    /// meanwhile.
+    fn quantile_data(&self, symbol: &str) -> Result<Ref<'_, String, SymbolData>, AppError> {
+        let not_found = || AppError::SymbolNotFound(symbol.to_string());
+        loop {
+            let data = self.symbols.get(symbol).ok_or_else(not_found)?;
+            if data.has_quantiles() {
+                return Ok(data);
+            }
+            drop(data);
+
+            let _building = self
+                .quantile_build_lock
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let source = {
+                let data = self.symbols.get(symbol).ok_or_else(not_found)?;
+                if data.has_quantiles() {
+                    // Built by the query we waited for.
+                    return Ok(data);
+                }
+                data.quantile_source()
+            };
+            let tree = source.build();
+
+            let mut data = self.symbols.get_mut(symbol).ok_or_else(not_found)?;
+            // Only fails if the symbol was reset or re-created in the meantime.
+            if data.install_quantiles(source, tree) {
+                return Ok(data.downgrade());
+            }
+        }
+    }
+
+    /// Estimates quantiles over the last `window_size` points of a symbol from
+    /// per-chunk sketches, with a rank error of at most `accuracy` (a fraction of
+    /// the window, at least `sketch_tree::MIN_ACCURACY`). Each value is a point of
+    /// the window; the reported `rank_error` is the bound actually achieved.
+    pub fn get_approx_quantiles(
+        &self,
+        symbol: &str,
+        window_size: usize,
+        qs: &[f64],
+        accuracy: f64,
+    ) -> Result<QuantileStats, AppError> {
+        let data = self
+            .symbols
+            .get(symbol)
+            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+
+        let actual_window_size = window_size.min(data.retained_len());
+        if actual_window_size == 0 {
+            return Err(AppError::NotEnoughData);
+        }
+        let end = data.len() - 1;
+        let start = data.len() - actual_window_size;
+
+        let approx =
+            data.approx_quantiles(start, end, qs, SketchTree::level_for_accuracy(accuracy));
+        Ok(QuantileStats {
+            count: actual_window_size as u64,
+            quantiles: qs.iter().copied().zip(approx.values).collect(),
+            rank_error: Some(approx.rank_error),
+        })
+    }
+
+    /// Retrieves statistics for all values whose timestamps fall within
+    /// `[since, until]` (inclusive, milliseconds since the Unix epoch).
+    /// The time bounds are resolved to indices by binary search.
+    pub fn get_stats_between(
+        &self,
+        symbol: &str,
+        since: u64,
+        until: u64,
+    ) -> Result<SymbolStats, AppError> {
+        let data = self
+            .symbols
+            .get(symbol)
+            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+
+        let start_index = data.partition_point(|ts| ts < since);
+        let end_exclusive = data.partition_point(|ts| ts <= until);
+        if start_index >= end_exclusive {
+            return Err(AppError::NotEnoughData);
+        }
+
+        // Evicted points are older than every retained one, so they may have
+        // fallen within the range only if it starts at the oldest retained point.
+        Ok(SymbolStats {
+            truncated: start_index == data.first_index() && data.first_index() > 0,
+            ..data.stats_for_range(start_index, end_exclusive - 1)?
+        })
+    }
+
+    /// Retrieves statistics for the absolute index range `[start, end]` (inclusive)
+    /// of a symbol's history, where index 0 is the first value ever added.
+    pub fn get_range_stats(
+        &self,
+        symbol: &str,
+        start: usize,
+        end: usize,
+    ) -> Result<RangeStats, AppError> {
+        if start > end {
+            return Err(AppError::BadRequest(format!(
+                "start ({}) must not be greater than end ({})",
+                start, end
+            )));
+        }
+
+        let data = self
+            .symbols
+            .get(symbol)
+            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+
+        let total_points = data.len();
+        if end >= total_points {
+            return Err(AppError::IndexOutOfRange {
+                start,
+                end,
+                len: total_points,
+            });
+        }
+        if start < data.first_index() {
+            return Err(AppError::IndexEvicted {
+                start,
+                first: data.first_index(),
+            });
+        }
+
+        let stats_node = data.query(start, end);
+        if stats_node.count == 0 {
+            return Err(AppError::NotEnoughData);
+        }
+
+        Ok(RangeStats {
+            min: stats_node.min,
+            max: stats_node.max,
+            avg: stats_node.mean,
+            var: stats_node.m2 / stats_node.count as f64,
+            count: stats_node.count,
+        })
+    }
+}
+
+/// Data specific to one financial symbol.
+///
+/// Values are addressed by their absolute index, where index 0 is the first value
+/// ever added. With a retention limit, `values`, `timestamps` and the tree act as a
+/// circular buffer: absolute index `i` lives in slot `i % limit`, and only the
+/// `limit` most recent points remain queryable.
+pub struct SymbolData {
+    values: Vec<f64>,
+    /// Milliseconds since the Unix epoch, one per entry in `values`.
+    timestamps: Vec<u64>,
+    tree: SegmentTree,
+    /// Answers order-statistics queries over the same slots as `tree`. Built on
+    /// the first exact quantile query, so that symbols which never get one pay
+    /// neither its memory nor the cost of keeping it sorted. It takes 8 bytes per
+    /// slot for each of its log16(N) levels (48 bytes per slot at 100 million
+    /// points) and is kept until the symbol is reset or removed.
+    quantiles: Option<QuantileTree>,
+    /// Answers approximate quantile queries over the same slots as `tree`.
+    sketches: SketchTree,
+    /// The total number of points ever added, including overwritten ones.
+    total_points: usize,
+    /// The absolute index of the oldest retained point.
+    first_index: usize,
+    /// The maximum number of points retained, or `None` if unbounded.
+    retention: Option<usize>,
+    /// When the symbol was created, in milliseconds since the Unix epoch.
+    created_at: u64,
+    /// When data was last appended, in milliseconds since the Unix epoch.
+    updated_at: u64,
+    /// Tells this data apart from the symbol's data before it was reset, or
+    /// removed and re-created, so a `QuantileSource` isn't applied to the wrong one.
+    generation: u64,
+}
+
+/// A copy of a symbol's slots, from which its exact-quantile tree is built
+/// without holding the symbol's lock (see `SymbolData::install_quantiles`).
+pub struct QuantileSource {
+    generation: u64,
+    /// The number of points the symbol held when its slots were copied.
+    total_points: usize,
+    values: Vec<f64>,
+}
+
+impl QuantileSource {
+    /// Sorts the copied slots into a tree.
+    pub fn build(&self) -> QuantileTree {
+        QuantileTree::from_values(&self.values)
+    }
+}
+
+impl SymbolData {
+    /// Creates empty symbol data whose storage starts at `starting_capacity`
+    /// points (or the retention limit, if that is smaller) and grows on demand.
+    pub fn new(retention: Option<usize>, starting_capacity: usize) -> Self {
+        let capacity = retention
+            .map_or(starting_capacity, |limit| limit.min(starting_capacity))
+            .max(1);
+        let now = now_millis();
+        Self {
+            values: Vec::with_capacity(capacity),
+            timestamps: Vec::with_capacity(capacity),
+            tree: SegmentTree::new(capacity),
+            quantiles: None,
+            sketches: SketchTree::new(),
+            total_points: 0,
+            first_index: 0,
+            retention,
+            created_at: now,
+            updated_at: now,
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Rebuilds symbol data from its retained values and timestamps (oldest first),
+    /// where `total_points` is the number of points ever added. If `retention` is
+    /// smaller than the number of values, only the newest ones are kept. The tree
+    /// is built bottom-up in a single O(N) pass.
+    pub fn restore(
+        retention: Option<usize>,
+        total_points: usize,
+        mut values: Vec<f64>,
+        mut timestamps: Vec<u64>,
+        created_at: u64,
+        updated_at: u64,
+    ) -> Self {
+        if let Some(limit) = retention {
+            let excess = values.len().saturating_sub(limit);
+            values.drain(..excess);
+            timestamps.drain(..excess);
+        }
+        let first_index = total_points - values.len();
+
+        // A bounded buffer whose history has already been trimmed is laid out by
+        // slot, so that later appends continue the circular order.
+        if let Some(limit) = retention.filter(|_| first_index > 0) {
+            let mut value_slots = vec![0.0; limit];
+            let mut timestamp_slots = vec![0; limit];
+            for (offset, (&value, &timestamp)) in values.iter().zip(&timestamps).enumerate() {
+                let slot = (first_index + offset) % limit;
+                value_slots[slot] = value;
+                timestamp_slots[slot] = timestamp;
+            }
+            values = value_slots;
+            timestamps = timestamp_slots;
+        }
+
+        let tree = SegmentTree::from_values(&values);
+        let sketches = SketchTree::from_values(&values);
+        Self {
+            values,
+            timestamps,
+            tree,
+            quantiles: None,
+            sketches,
+            total_points,
+            first_index,
+            retention,
+            created_at,
+            updated_at,
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// The total number of points ever added. The last point has index `len() - 1`.
+    pub fn len(&self) -> usize {
+        self.total_points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_points == 0
+    }
+
+    /// The absolute index of the oldest point that is still retained.
+    pub fn first_index(&self) -> usize {
+        self.first_index
+    }
+
+    /// The number of points currently retained.
+    pub fn retained_len(&self) -> usize {
+        self.total_points - self.first_index()
+    }
+
+    /// The maximum number of points retained, or `None` if unbounded.
+    pub fn retention(&self) -> Option<usize> {
+        self.retention
+    }
+
+    /// Returns the value at absolute index `index`, if it is still retained.
+    pub fn value_at(&self, index: usize) -> Option<f64> {
+        (self.first_index()..self.total_points)
+            .contains(&index)
+            .then(|| self.values[self.slot(index)])
+    }
+
+    /// Returns the timestamp at absolute index `index`, if it is still retained.
+    pub fn timestamp_at(&self, index: usize) -> Option<u64> {
+        (self.first_index()..self.total_points)
+            .contains(&index)
+            .then(|| self.timestamps[self.slot(index)])
+    }
+
+    pub fn last_timestamp(&self) -> Option<u64> {
+        self.total_points
+            .checked_sub(1)
+            .and_then(|index| self.timestamp_at(index))
+    }
+
+    /// Returns the retained values, oldest first.
+    pub fn retained_values(&self) -> impl Iterator<Item = f64> + '_ {
+        (self.first_index..self.total_points).map(|index| self.values[self.slot(index)])
+    }
+
+    /// Returns the retained timestamps, oldest first.
+    pub fn retained_timestamps(&self) -> impl Iterator<Item = u64> + '_ {
+        (self.first_index..self.total_points).map(|index| self.timestamps[self.slot(index)])
+    }
+
+    /// When the symbol was created, in milliseconds since the Unix epoch.
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// When data was last appended, in milliseconds since the Unix epoch.
+    pub fn updated_at(&self) -> u64 {
+        self.updated_at
+    }
+
+    /// Describes this symbol's data.
+    pub fn info(&self, symbol: &str) -> SymbolInfo {
+        SymbolInfo {
+            symbol: symbol.to_string(),
+            points: self.len(),
+            retained: self.retained_len(),
+            first_index: self.first_index(),
+            capacity: self.capacity(),
+            retention: self.retention(),
+            first_value: self.value_at(self.first_index()),
+            last_value: self
+                .total_points
+                .checked_sub(1)
+                .and_then(|index| self.value_at(index)),
+            created_at: self.created_at(),
+            updated_at: self.updated_at(),
+            memory_bytes: self.memory_footprint(),
+        }
+    }
+
+    /// The number of slots allocated for values, timestamps and tree leaves.
+    pub fn capacity(&self) -> usize {
+        self.tree.capacity()
+    }
+
+    /// The approximate number of bytes allocated for this symbol's data.
+    pub fn memory_footprint(&self) -> usize {
+        self.values.capacity() * std::mem::size_of::<f64>()
+            + self.timestamps.capacity() * std::mem::size_of::<u64>()
+            + self.tree.memory_footprint()
+            + self
+                .quantiles
+                .as_ref()
+                .map_or(0, QuantileTree::memory_footprint)
+            + self.sketches.memory_footprint()
+    }
+
+    /// Ensures storage for at least `required` slots. Storage grows geometrically
+    /// (at least doubling) so that appends stay amortized O(1), but never beyond
+    /// the retention limit. The tree is rebuilt bottom-up in O(N) when it grows.
+    pub fn reserve(&mut self, required: usize) {
+        let capacity = self.capacity();
+        if required <= capacity {
+            return;
+        }
+
+        let mut new_capacity = (capacity * 2).max(required);
+        if let Some(limit) = self.retention {
+            new_capacity = new_capacity.min(limit);
+        }
+        if new_capacity <= capacity {
+            return;
+        }
+
+        self.values.reserve_exact(new_capacity - self.values.len());
+        self.timestamps
+            .reserve_exact(new_capacity - self.timestamps.len());
+        self.tree.grow(new_capacity, &self.values);
+    }
+
+    /// Maps an absolute index to its slot in the underlying storage.
+    fn slot(&self, index: usize) -> usize {
+        match self.retention {
+            Some(limit) => index % limit,
+            None => index - self.first_index,
+        }
+    }
+
+    /// Appends a batch of values and their timestamps, overwriting the oldest
+    /// retained points once the retention limit is reached.
+    pub fn append(&mut self, batch_values: &[f64], batch_timestamps: &[u64]) {
+        self.updated_at = now_millis();
+
+        let Some(limit) = self.retention else {
+            let start_index = self.values.len();
+            self.reserve(start_index + batch_values.len());
+            self.values.extend_from_slice(batch_values);
+            self.timestamps.extend_from_slice(batch_timestamps);
+            self.tree
+                .batch_update(start_index, batch_values, &self.values);
+            if let Some(quantiles) = &mut self.quantiles {
+                quantiles.update(start_index, batch_values.len(), &self.values);
+            }
+            self.sketches
+                .update(start_index, batch_values.len(), &self.values);
+            self.total_points += batch_values.len();
+            return;
+        };
+
+        // Only the newest `limit` points of an oversized batch would survive.
+        let skipped = batch_values.len().saturating_sub(limit);
+        let batch_values = &batch_values[skipped..];
+        let batch_timestamps = &batch_timestamps[skipped..];
+        let first_new_index = self.total_points + skipped;
+        self.reserve(first_new_index + batch_values.len());
 
-impl Default for Store {
-    fn default() -> Self {
-        Self::new()
+        // Once the buffer wraps, every slot is written by index rather than
+        // appended, so fill it to its full size first.
+        if first_new_index + batch_values.len() > limit && self.values.len() < limit {
+            self.values.resize(limit, 0.0);
+            self.timestamps.resize(limit, 0);
+        }
+
+        let mut offset = 0;
+        while offset < batch_values.len() {
+            let slot = (first_new_index + offset) % limit;
+            let run = (limit - slot).min(batch_values.len() - offset);
+            let run_values = &batch_values[offset..offset + run];
+            let run_timestamps = &batch_timestamps[offset..offset + run];
+
+            if slot == self.values.len() {
+                self.values.extend_from_slice(run_values);
+                self.timestamps.extend_from_slice(run_timestamps);
+            } else {
+                self.values[slot..slot + run].copy_from_slice(run_values);
+                self.timestamps[slot..slot + run].copy_from_slice(run_timestamps);
+            }
+            self.tree.batch_update(slot, run_values, &self.values);
+            if let Some(quantiles) = &mut self.quantiles {
+                quantiles.update(slot, run, &self.values);
+            }
+            self.sketches.update(slot, run, &self.values);
+
+            offset += run;
+        }
+
+        self.total_points += skipped + batch_values.len();
+        self.first_index = self
+            .first_index
+            .max(self.total_points.saturating_sub(limit));
     }
-}
 
-impl Store {
-    pub fn new() -> Self {
-        Self {
-            symbols: DashMap::new(),
+    /// Returns the aggregate node over the absolute index range `[start, end]`,
+    /// which must lie within the retained points. A range that wraps around the
+    /// end of the circular buffer is answered as two tree queries.
+    pub fn query(&self, start: usize, end: usize) -> Node {
+        let (start_slot, end_slot) = (self.slot(start), self.slot(end));
+        if start_slot <= end_slot {
+            self.tree.query(start_slot, end_slot)
+        } else {
+            // Only a bounded buffer can wrap, so the limit is always set here.
+            let limit = self.retention.unwrap_or(self.values.len());
+            self.tree.query(start_slot, limit - 1) + self.tree.query(0, end_slot)
         }
     }
 
-    /// Adds a batch of values for a given symbol.
-    /// This now contains the core update logic.
-    pub fn add_batch(&self, symbol: &str, batch_values: &[f64]) -> Result<(), AppError> {
-        // If the symbol doesn't exist yet and we are at capacity, reject the request.
-        if !self.symbols.contains_key(symbol) && self.symbols.len() >= MAX_SYMBOLS {
-            return Err(AppError::BadRequest(format!(
-                "Maximum number of unique symbols ({}) reached.",
-                MAX_SYMBOLS
-            )));
+    /// Whether the tree behind `select` has been built.
+    pub fn has_quantiles(&self) -> bool {
+        self.quantiles.is_some()
+    }
+
+    /// Copies the slots, so that the tree behind `select` can be built from them
+    /// without holding the symbol's lock.
+    pub fn quantile_source(&self) -> QuantileSource {
+        QuantileSource {
+            generation: self.generation,
+            total_points: self.total_points,
+            values: self.values.clone(),
         }
+    }
 
-        let mut symbol_data_guard =
-            self.symbols
-                .entry(symbol.to_string())
-                .or_insert_with(|| SymbolData {
-                    values: Vec::with_capacity(STARTING_CAPACITY),
-                    tree: SegmentTree::new(STARTING_CAPACITY),
-                });
+    /// Installs `tree`, built from `source`, as the tree behind `select`, after
+    /// re-sorting its chunks over the slots written since `source` was copied.
+    /// From then on, appends keep it up to date. Returns `false`, leaving the
+    /// tree unbuilt, if `source` was copied from data this has since replaced.
+    pub fn install_quantiles(&mut self, source: QuantileSource, mut tree: QuantileTree) -> bool {
+        if self.quantiles.is_some() {
+            return true;
+        }
+        if source.generation != self.generation {
+            return false;
+        }
 
-        let SymbolData { values, tree } = &mut *symbol_data_guard;
+        // Catch up in the same runs as `append`, skipping points that have
+        // since been overwritten themselves.
+        let mut index = source.total_points.max(self.first_index);
+        while index < self.total_points {
+            let slot = self.slot(index);
+            let run = match self.retention {
+                Some(limit) => (limit - slot).min(self.total_points - index),
+                None => self.total_points - index,
+            };
+            tree.update(slot, run, &self.values);
+            index += run;
+        }
+        self.quantiles = Some(tree);
+        true
+    }
 
-        let start_index = values.len();
-        values.extend_from_slice(batch_values);
-        tree.batch_update(start_index, batch_values, values);
+    /// Returns the `rank`-th smallest value (0-based) in the absolute index range
+    /// `[start, end]`, which must lie within the retained points.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no tree has been installed with `install_quantiles`.
+    pub fn select(&self, start: usize, end: usize, rank: usize) -> f64 {
+        let quantiles = self
+            .quantiles
+            .as_ref()
+            .expect("install_quantiles must be called before select");
+        let (start_slot, end_slot) = (self.slot(start), self.slot(end));
+        if start_slot <= end_slot {
+            quantiles.select(&[(start_slot, end_slot)], rank, &self.values)
+        } else {
+            let limit = self.retention.unwrap_or(self.values.len());
+            quantiles.select(
+                &[(start_slot, limit - 1), (0, end_slot)],
+                rank,
+                &self.values,
+            )
+        }
+    }
 
-        Ok(())
+    /// Estimates the quantiles `qs` of the absolute index range `[start, end]`,
+    /// which must lie within the retained points, from sketches up to `max_level`.
+    pub fn approx_quantiles(
+        &self,
+        start: usize,
+        end: usize,
+        qs: &[f64],
+        max_level: usize,
+    ) -> ApproxQuantiles {
+        let (start_slot, end_slot) = (self.slot(start), self.slot(end));
+        if start_slot <= end_slot {
+            self.sketches
+                .quantiles(&[(start_slot, end_slot)], qs, max_level, &self.values)
+        } else {
+            let limit = self.retention.unwrap_or(self.values.len());
+            self.sketches.quantiles(
+                &[(start_slot, limit - 1), (0, end_slot)],
+                qs,
+                max_level,
+                &self.values,
+            )
+        }
     }
 
-    /// Retrieves and calculates full statistics for a given symbol and window.
-    /// This now contains the final avg/var calculations.
-    pub fn get_stats(&self, symbol: &str, window_size: usize) -> Result<SymbolStats, AppError> {
-        let data = self
-            .symbols
-            .get(symbol)
-            .ok_or_else(|| AppError::SymbolNotFound(symbol.to_string()))?;
+    /// Returns the absolute index of the first retained point whose timestamp does
+    /// not satisfy `pred`, assuming `pred` holds for a prefix of the retained points.
+    pub fn partition_point(&self, pred: impl Fn(u64) -> bool) -> usize {
+        let (mut low, mut high) = (self.first_index(), self.total_points);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if pred(self.timestamps[self.slot(mid)]) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
 
-        let total_points = data.values.len();
+    /// Computes full statistics over the last `window_size` points, or over all
+    /// retained points (flagged as truncated) if fewer are available.
+    fn window_stats(&self, window_size: usize) -> Result<SymbolStats, AppError> {
+        let total_points = self.len();
         if total_points == 0 {
             return Err(AppError::NotEnoughData);
         }
 
-        // Use the full dataset if the window is larger than available points.
-        let actual_window_size = window_size.min(total_points);
+        // Use all retained data if the window is larger than available points.
+        let actual_window_size = window_size.min(self.retained_len());
         if actual_window_size == 0 {
             return Err(AppError::NotEnoughData);
         }
 
-        let start_index = total_points.saturating_sub(actual_window_size);
-        let stats_node = data.tree.query(start_index, total_points - 1);
+        let start_index = total_points - actual_window_size;
+        Ok(SymbolStats {
+            truncated: actual_window_size < window_size,
+            ..self.stats_for_range(start_index, total_points - 1)?
+        })
+    }
+
+    /// Computes full statistics over the absolute index range `[start, end]`,
+    /// reporting the value at `end` as `last`.
+    fn stats_for_range(&self, start: usize, end: usize) -> Result<SymbolStats, AppError> {
+        let stats_node = self.query(start, end);
 
         if stats_node.count == 0 {
             return Err(AppError::NotEnoughData);
         }
 
-        let last_value = data.values[total_points - 1];
+        let last_value = self.values[self.slot(end)];
         let avg = stats_node.mean;
         let variance = if stats_node.count > 0 {
             stats_node.m2 / stats_node.count as f64
@@ -102,14 +1466,55 @@ impl Store {
             last: last_value,
             avg,
             var: variance,
+            skewness: stats_node.skewness(),
+            kurtosis: stats_node.excess_kurtosis(),
+            count: stats_node.count,
+            sum: stats_node.mean * stats_node.count as f64,
+            start_index: start,
+            end_index: end,
+            truncated: false,
+            ddof: 0,
         })
     }
 }
 
-/// Data specific to one financial symbol.
-pub struct SymbolData {
-    pub values: Vec<f64>,
-    pub tree: SegmentTree,
+/// Checks that a batch's timestamps are in non-decreasing order.
+fn check_batch_timestamps(batch_timestamps: &[u64]) -> Result<(), AppError> {
+    if batch_timestamps.windows(2).any(|w| w[1] < w[0]) {
+        return Err(AppError::BadRequest(
+            "timestamps must be non-decreasing".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves the timestamps of a validated batch of `len` values that follows a
+/// symbol's last stored timestamp, `last`. Given timestamps must not precede it;
+/// a batch without timestamps is stamped with `now`, or `last` if that is later.
+fn continue_timestamps(
+    timestamps: Option<Cow<'_, [u64]>>,
+    len: usize,
+    last: Option<u64>,
+    now: u64,
+) -> Result<Cow<'_, [u64]>, AppError> {
+    match (timestamps, last) {
+        (Some(timestamps), Some(last)) if timestamps[0] < last => {
+            Err(AppError::BadRequest(format!(
+                "timestamps must not precede the last stored timestamp ({})",
+                last
+            )))
+        }
+        (Some(timestamps), _) => Ok(timestamps),
+        (None, last) => Ok(Cow::Owned(vec![now.max(last.unwrap_or(0)); len])),
+    }
+}
+
+/// Returns the current wall-clock time in milliseconds since the Unix epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
 }
 
 #[cfg(test)]
@@ -143,6 +1548,67 @@ mod tests {
         assert_eq!(stats.last, 25.0);
         fuzzy_assert_eq(stats.avg, 15.0);
         fuzzy_assert_eq(stats.var, 50.0);
+
+        // The window is reported as clamped to the 5 points that exist.
+        assert_eq!(stats.count, 5);
+        fuzzy_assert_eq(stats.sum, 75.0);
+        assert_eq!((stats.start_index, stats.end_index), (0, 4));
+        assert!(stats.truncated);
+        assert!(!store.get_stats(symbol, 5).unwrap().truncated);
+    }
+
+    #[test]
+    fn test_get_stats_for_windows_matches_single_windows() {
+        // Arrange
+        let store = Store::new();
+        let values: Vec<f64> = (0..1_000).map(|i| ((i * 37) % 101) as f64).collect();
+        store.add_batch("MULTI", &values).unwrap();
+        let windows = [10, 100, 1_000, 10_000];
+
+        // Act
+        let all_stats = store.get_stats_for_windows("MULTI", &windows).unwrap();
+
+        // Assert: Each result matches its own request, in the order given.
+        assert_eq!(all_stats.len(), windows.len());
+        for (stats, &window) in all_stats.iter().zip(&windows) {
+            let expected = store.get_stats("MULTI", window).unwrap();
+            assert_eq!(stats.count, expected.count);
+            assert_eq!(stats.start_index, expected.start_index);
+            assert_eq!(stats.truncated, expected.truncated);
+            fuzzy_assert_eq(stats.avg, expected.avg);
+            fuzzy_assert_eq(stats.var, expected.var);
+        }
+        assert!(all_stats[3].truncated);
+        assert!(matches!(
+            store.get_stats_for_windows("NOPE", &windows),
+            Err(AppError::SymbolNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_sample_variance_with_ddof() {
+        // Arrange
+        let store = Store::new();
+        store
+            .add_batch("DDOF", &[10.0, 20.0, 5.0, 15.0, 25.0])
+            .unwrap();
+        let stats = store.get_stats("DDOF", 10).unwrap();
+
+        // Act
+        let population = stats.with_ddof(0).unwrap();
+        let sample = stats.with_ddof(1).unwrap();
+
+        // Assert: The squared deviations sum to 250.
+        assert_eq!(population.ddof, 0);
+        fuzzy_assert_eq(population.var, 250.0 / 5.0);
+        assert_eq!(sample.ddof, 1);
+        fuzzy_assert_eq(sample.var, 250.0 / 4.0);
+        fuzzy_assert_eq(sample.stddev(), 62.5_f64.sqrt());
+        fuzzy_assert_eq(sample.with_ddof(0).unwrap().var, 50.0);
+
+        // A single point has no sample variance.
+        let single = store.get_stats("DDOF", 1).unwrap();
+        assert!(matches!(single.with_ddof(1), Err(AppError::NotEnoughData)));
     }
 
     #[test]
@@ -160,4 +1626,589 @@ mod tests {
             _ => panic!("Expected SymbolNotFound error"),
         }
     }
+
+    #[test]
+    fn test_get_range_stats_for_past_range() {
+        // Arrange
+        let store = Store::new();
+        let symbol = "TEST";
+        let values = vec![10.0, 20.0, 5.0, 15.0, 25.0];
+        store.add_batch(symbol, &values).unwrap();
+
+        // Act: Query the inner range [20.0, 5.0, 15.0].
+        let stats = store.get_range_stats(symbol, 1, 3).unwrap();
+
+        // Assert
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.min, 5.0);
+        assert_eq!(stats.max, 20.0);
+        let expected_mean = 40.0 / 3.0;
+        fuzzy_assert_eq(stats.avg, expected_mean);
+        let expected_var = ((20.0 - expected_mean).powi(2)
+            + (5.0 - expected_mean).powi(2)
+            + (15.0 - expected_mean).powi(2))
+            / 3.0;
+        fuzzy_assert_eq(stats.var, expected_var);
+    }
+
+    #[test]
+    fn test_get_range_stats_out_of_range() {
+        // Arrange
+        let store = Store::new();
+        store.add_batch("TEST", &[1.0, 2.0, 3.0]).unwrap();
+
+        // Act: The last valid index is 2.
+        let result = store.get_range_stats("TEST", 1, 3);
+
+        // Assert
+        match result.err().unwrap() {
+            AppError::IndexOutOfRange { start, end, len } => {
+                assert_eq!((start, end, len), (1, 3, 3));
+            }
+            _ => panic!("Expected IndexOutOfRange error"),
+        }
+    }
+
+    #[test]
+    fn test_get_stats_between_resolves_time_bounds() {
+        // Arrange: one point per second.
+        let store = Store::new();
+        let symbol = "TEST";
+        let values = vec![10.0, 20.0, 5.0, 15.0, 25.0];
+        let timestamps = vec![1_000, 2_000, 3_000, 4_000, 5_000];
+        store
+            .add_batch_with_timestamps(symbol, &values, &timestamps)
+            .unwrap();
+
+        // Act: Bounds fall between and on stored timestamps.
+        let stats = store.get_stats_between(symbol, 1_500, 4_000).unwrap();
+
+        // Assert: Only [20.0, 5.0, 15.0] are inside the window.
+        assert_eq!(stats.min, 5.0);
+        assert_eq!(stats.max, 20.0);
+        assert_eq!(stats.last, 15.0);
+        fuzzy_assert_eq(stats.avg, 40.0 / 3.0);
+        assert_eq!((stats.start_index, stats.end_index), (1, 3));
+        assert!(!stats.truncated);
+
+        // A window with no points in it has no stats.
+        let result = store.get_stats_between(symbol, 5_001, 9_000);
+        assert!(matches!(result, Err(AppError::NotEnoughData)));
+    }
+
+    #[test]
+    fn test_atomic_batches_are_checked_as_if_applied_in_order() {
+        // Arrange: Room for one more symbol, and "OLD" has data up to t = 5000.
+        let store = Store::new().with_limits(LimitsConfig {
+            max_symbols: 2,
+            ..Default::default()
+        });
+        store
+            .add_batch_with_timestamps("OLD", &[1.0], &[5_000])
+            .unwrap();
+        let batch = |symbol, timestamps| SymbolBatch {
+            symbol,
+            values: &[1.0, 2.0][..],
+            timestamps: Some(timestamps),
+            policy: InvalidValuePolicy::Reject,
+            capacity_hint: None,
+        };
+        let rejected_index = |batches: &[SymbolBatch]| match store.add_batches_atomic(batches) {
+            Err(AppError::BatchRejected { index, .. }) => index,
+            other => panic!("Expected BatchRejected, got {:?}", other),
+        };
+
+        // Act & Assert: A second "OLD" batch that overlaps the first, a third
+        // symbol, a missing timestamp and a NaN each reject the whole request.
+        let old = batch("OLD", &[6_000, 7_000][..]);
+        let new = batch("NEW", &[1_000, 2_000][..]);
+        assert_eq!(rejected_index(&[old, batch("OLD", &[6_500, 8_000][..])]), 1);
+        assert_eq!(
+            rejected_index(&[new, old, batch("OTHER", &[1_000, 2_000][..])]),
+            2
+        );
+        assert_eq!(rejected_index(&[new, batch("NEW", &[3_000][..])]), 1);
+        let nan = SymbolBatch {
+            values: &[f64::NAN, 3.0][..],
+            ..batch("NEW", &[3_000, 4_000][..])
+        };
+        assert_eq!(rejected_index(&[old, new, nan]), 2);
+
+        // Nothing was added.
+        assert_eq!(store.symbols.get("OLD").unwrap().len(), 1);
+        assert!(!store.symbols.contains_key("NEW"));
+
+        // Act: Batches that continue each other, including a stamped one.
+        let dropped = store
+            .add_batches_atomic(&[
+                old,
+                new,
+                batch("NEW", &[2_000, 3_000][..]),
+                SymbolBatch {
+                    timestamps: None,
+                    ..old
+                },
+            ])
+            .unwrap();
+
+        // Assert
+        assert_eq!(dropped, vec![Vec::<usize>::new(); 4]);
+        let data = store.symbols.get("OLD").unwrap();
+        assert_eq!(data.len(), 5);
+        assert!(data.last_timestamp().unwrap() >= 7_000);
+        assert_eq!(store.symbols.get("NEW").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_rejects_out_of_order_timestamps() {
+        // Arrange
+        let store = Store::new();
+        store
+            .add_batch_with_timestamps("TEST", &[1.0, 2.0], &[1_000, 2_000])
+            .unwrap();
+
+        // Act & Assert: Unordered within a batch.
+        let result = store.add_batch_with_timestamps("TEST", &[3.0, 4.0], &[4_000, 3_000]);
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+
+        // Act & Assert: Earlier than the last stored timestamp.
+        let result = store.add_batch_with_timestamps("TEST", &[3.0], &[1_500]);
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+
+        // Nothing from the rejected batches was stored.
+        assert_eq!(store.symbols.get("TEST").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_stamped_batches_follow_future_timestamps() {
+        // Arrange: A stored timestamp an hour ahead of the wall clock.
+        let store = Store::new();
+        let future = now_millis() + 3_600_000;
+        store
+            .add_batch_with_timestamps("TEST", &[1.0], &[future])
+            .unwrap();
+
+        // Act: Batches without timestamps are stamped by the store.
+        store.add_batch("TEST", &[2.0, 3.0]).unwrap();
+
+        // Assert: They were clamped to the last stored timestamp, not rejected.
+        let data = store.symbols.get("TEST").unwrap();
+        assert!(data.retained_timestamps().eq([future; 3]));
+        drop(data);
+
+        // Explicit timestamps must still not go back in time.
+        let result = store.add_batch_with_timestamps("TEST", &[4.0], &[future - 1]);
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_subscribers_are_notified_of_changes() {
+        // Arrange
+        let store = Store::new();
+        let mut updates = store.subscribe();
+
+        // Act
+        store.add_batch("A", &[1.0, 2.0]).unwrap();
+        store.add_batch("A", &[f64::NAN]).unwrap_err();
+        store
+            .add_batch_with_policy("B", &[f64::NAN], &[1], InvalidValuePolicy::Drop)
+            .unwrap();
+        store.reset_symbol("A").unwrap();
+        store.remove_symbol("A").unwrap();
+
+        // Assert: Rejected and fully dropped batches change nothing, so they
+        // are not announced.
+        for expected in ["A", "A", "A"] {
+            assert_eq!(&*updates.try_recv().unwrap(), expected);
+        }
+        assert!(updates.try_recv().is_err());
+    }
+
+    /// Builds a store where every symbol keeps at most `limit` points.
+    fn store_with_retention(limit: usize) -> Store {
+        Store::new().with_retention(RetentionConfig {
+            max_points: limit,
+            ..Default::default()
+        })
+    }
+
+    /// Computes (min, max, mean, population variance) of a slice by brute force.
+    fn brute_force_stats(values: &[f64]) -> (f64, f64, f64, f64) {
+        let n = values.len() as f64;
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let mean = values.iter().sum::<f64>() / n;
+        let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        (min, max, mean, var)
+    }
+
+    /// Computes the population skewness and excess kurtosis of a slice directly.
+    fn brute_force_shape(values: &[f64]) -> (f64, f64) {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let moment = |k: i32| values.iter().map(|v| (v - mean).powi(k)).sum::<f64>() / n;
+        let (m2, m3, m4) = (moment(2), moment(3), moment(4));
+        if m2 == 0.0 {
+            return (0.0, 0.0);
+        }
+        (m3 / m2.powf(1.5), m4 / (m2 * m2) - 3.0)
+    }
+
+    #[test]
+    fn test_ring_buffer_stats_after_wrap_around() {
+        // Arrange: Keep 7 points, and add 50 in uneven batches so the buffer
+        // wraps several times and batches straddle the end of the buffer.
+        let store = store_with_retention(7);
+        let symbol = "RING";
+        let all_values: Vec<f64> = (0..50).map(|i| ((i * 37) % 101) as f64).collect();
+        for batch in all_values.chunks(3) {
+            store.add_batch(symbol, batch).unwrap();
+        }
+
+        // Act & Assert: Every window, including ones larger than the retained
+        // data, matches a brute-force calculation over the retained tail.
+        for window in 1..=10 {
+            let stats = store.get_stats(symbol, window).unwrap();
+            let expected_window = &all_values[50 - window.min(7)..];
+            let (min, max, mean, var) = brute_force_stats(expected_window);
+            assert_eq!(stats.min, min, "min mismatch for window {}", window);
+            assert_eq!(stats.max, max, "max mismatch for window {}", window);
+            assert_eq!(stats.last, all_values[49]);
+            fuzzy_assert_eq(stats.avg, mean);
+            fuzzy_assert_eq(stats.var, var);
+            let (skewness, kurtosis) = brute_force_shape(expected_window);
+            fuzzy_assert_eq(stats.skewness, skewness);
+            fuzzy_assert_eq(stats.kurtosis, kurtosis);
+        }
+
+        let data = store.symbols.get(symbol).unwrap();
+        assert_eq!(data.len(), 50);
+        assert_eq!(data.first_index(), 43);
+        assert_eq!(data.retained_len(), 7);
+    }
+
+    #[test]
+    fn test_ring_buffer_batch_larger_than_retention() {
+        // Arrange: A partially filled buffer receives a batch bigger than itself.
+        let store = store_with_retention(5);
+        store.add_batch("RING", &[1.0, 2.0, 3.0]).unwrap();
+        store
+            .add_batch("RING", &[10.0, 20.0, 30.0, 40.0, 50.0, 60.0])
+            .unwrap();
+
+        // Act
+        let stats = store.get_stats("RING", 100_000_000).unwrap();
+
+        // Assert: Only the newest five values remain.
+        assert_eq!(stats.min, 20.0);
+        assert_eq!(stats.max, 60.0);
+        assert_eq!(stats.last, 60.0);
+        fuzzy_assert_eq(stats.avg, 40.0);
+    }
+
+    #[test]
+    fn test_ring_buffer_range_and_time_queries() {
+        // Arrange: Points at t = 0, 1000, ..., 9000 with only 4 retained.
+        let store = store_with_retention(4);
+        let values: Vec<f64> = (0..10).map(|i| i as f64).collect();
+        let timestamps: Vec<u64> = (0..10).map(|i| i * 1_000).collect();
+        store
+            .add_batch_with_timestamps("RING", &values, &timestamps)
+            .unwrap();
+
+        // Act & Assert: Absolute indices still address the retained points.
+        let stats = store.get_range_stats("RING", 6, 8).unwrap();
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.min, 6.0);
+        assert_eq!(stats.max, 8.0);
+
+        // Overwritten indices are reported as evicted.
+        match store.get_range_stats("RING", 5, 8).err().unwrap() {
+            AppError::IndexEvicted { start, first } => assert_eq!((start, first), (5, 6)),
+            _ => panic!("Expected IndexEvicted error"),
+        }
+
+        // Time windows only see retained points.
+        let stats = store.get_stats_between("RING", 0, 7_000).unwrap();
+        assert_eq!(stats.min, 6.0);
+        assert_eq!(stats.last, 7.0);
+        assert_eq!((stats.start_index, stats.end_index), (6, 7));
+        assert!(stats.truncated);
+        assert!(
+            !store
+                .get_stats_between("RING", 7_000, 9_000)
+                .unwrap()
+                .truncated
+        );
+    }
+
+    #[test]
+    fn test_retention_per_symbol_override() {
+        // Arrange
+        let mut symbols = std::collections::HashMap::new();
+        symbols.insert("BOUNDED".to_string(), 2);
+        let store = Store::new().with_retention(RetentionConfig {
+            max_points: 0,
+            symbols,
+        });
+
+        // Act
+        store.add_batch("BOUNDED", &[1.0, 2.0, 3.0]).unwrap();
+        store.add_batch("UNBOUNDED", &[1.0, 2.0, 3.0]).unwrap();
+
+        // Assert
+        assert_eq!(store.symbols.get("BOUNDED").unwrap().retained_len(), 2);
+        assert_eq!(store.symbols.get("UNBOUNDED").unwrap().retained_len(), 3);
+    }
+
+    #[test]
+    fn test_storage_grows_geometrically() {
+        // Arrange: Start at 4 slots.
+        let store = Store::new().with_limits(LimitsConfig {
+            starting_capacity: 4,
+            ..Default::default()
+        });
+        let all_values: Vec<f64> = (0..100).map(|i| ((i * 37) % 101) as f64).collect();
+
+        // Act & Assert: Capacity doubles as single points arrive.
+        let mut capacities = Vec::new();
+        for value in &all_values {
+            store.add_batch("GROW", &[*value]).unwrap();
+            let capacity = store.symbols.get("GROW").unwrap().capacity();
+            if capacities.last() != Some(&capacity) {
+                capacities.push(capacity);
+            }
+        }
+        assert_eq!(capacities, vec![4, 8, 16, 32, 64, 128]);
+
+        // Stats are unaffected by the rebuilds.
+        let stats = store.get_stats("GROW", 100).unwrap();
+        let (min, max, mean, var) = brute_force_stats(&all_values);
+        assert_eq!((stats.min, stats.max), (min, max));
+        fuzzy_assert_eq(stats.avg, mean);
+        fuzzy_assert_eq(stats.var, var);
+
+        // A capacity hint sizes the storage in one step.
+        store.reserve("GROW", 1_000).unwrap();
+        assert_eq!(store.symbols.get("GROW").unwrap().capacity(), 1_000);
+    }
+
+    #[test]
+    fn test_storage_never_grows_past_retention() {
+        // Arrange
+        let store = store_with_retention(10).with_limits(LimitsConfig {
+            starting_capacity: 4,
+            ..Default::default()
+        });
+
+        // Act
+        store.add_batch("RING", &[1.0; 7]).unwrap();
+        store.reserve("RING", 1_000).unwrap();
+        store.add_batch("RING", &[2.0; 25]).unwrap();
+
+        // Assert
+        let data = store.symbols.get("RING").unwrap();
+        assert_eq!(data.capacity(), 10);
+        assert_eq!(data.retained_len(), 10);
+    }
+
+    #[test]
+    fn test_many_sparse_symbols_stay_small() {
+        // Arrange: Room for thousands of symbols with the default starting capacity.
+        let symbol_count = 5_000;
+        let store = Store::new().with_limits(LimitsConfig {
+            max_symbols: symbol_count,
+            ..Default::default()
+        });
+
+        // Act: Each symbol receives only a handful of ticks.
+        for i in 0..symbol_count {
+            store
+                .add_batch(&format!("SYM-{}", i), &[1.0, 2.0, 3.0])
+                .unwrap();
+        }
+
+        // Assert: Well under 16 KB per symbol, where preallocating a million
+        // points used to cost about 80 MB each.
+        let footprint = store.memory_footprint();
+        assert!(
+            footprint < symbol_count * 16 * 1024,
+            "{} symbols use {} bytes",
+            symbol_count,
+            footprint
+        );
+        assert_eq!(store.get_stats("SYM-42", 10).unwrap().avg, 2.0);
+    }
+
+    /// Computes a linearly interpolated quantile of a slice by sorting it.
+    fn brute_force_quantile(values: &[f64], q: f64) -> f64 {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let position = q * (sorted.len() - 1) as f64;
+        let lower = position.floor() as usize;
+        let upper = position.ceil() as usize;
+        sorted[lower] + (position - lower as f64) * (sorted[upper] - sorted[lower])
+    }
+
+    #[test]
+    fn test_quantiles_match_sorting() {
+        // Arrange
+        let store = Store::new();
+        let all_values: Vec<f64> = (0..3000).map(|i| ((i * 7919) % 1009) as f64).collect();
+        for batch in all_values.chunks(250) {
+            store.add_batch("Q", batch).unwrap();
+        }
+        let qs = [0.0, 0.25, 0.5, 0.95, 0.99, 1.0];
+
+        // Act & Assert
+        for window in [1, 2, 10, 999, 3000, 10_000] {
+            let stats = store.get_quantiles("Q", window, &qs).unwrap();
+            let expected_window = &all_values[3000 - window.min(3000)..];
+            assert_eq!(stats.count, expected_window.len() as u64);
+            for (q, value) in stats.quantiles {
+                fuzzy_assert_eq(value, brute_force_quantile(expected_window, q));
+            }
+        }
+
+        // The median of an even-sized window is the mean of the middle two.
+        store.add_batch("EVEN", &[4.0, 1.0, 3.0, 2.0]).unwrap();
+        let stats = store.get_quantiles("EVEN", 4, &[0.5]).unwrap();
+        assert_eq!(stats.quantiles, vec![(0.5, 2.5)]);
+    }
+
+    #[test]
+    fn test_quantiles_over_ring_buffer() {
+        // Arrange: Retain 500 points and wrap several times.
+        let store = store_with_retention(500);
+        let all_values: Vec<f64> = (0..2333).map(|i| ((i * 37) % 101) as f64).collect();
+        for batch in all_values.chunks(77) {
+            store.add_batch("RING", batch).unwrap();
+        }
+
+        // Act & Assert: Windows that do and do not wrap around the buffer.
+        for window in [1, 100, 233, 234, 499, 500, 1000] {
+            let stats = store
+                .get_quantiles("RING", window, &[0.1, 0.5, 0.9])
+                .unwrap();
+            let expected_window = &all_values[2333 - window.min(500)..];
+            for (q, value) in stats.quantiles {
+                fuzzy_assert_eq(value, brute_force_quantile(expected_window, q));
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantile_tree_is_built_on_first_query() {
+        // Arrange: A wrapped ring buffer that has never been asked for quantiles.
+        let store = store_with_retention(500);
+        let all_values: Vec<f64> = (0..1500).map(|i| ((i * 37) % 101) as f64).collect();
+        for batch in all_values[..1200].chunks(77) {
+            store.add_batch("RING", batch).unwrap();
+        }
+        let footprint = store.memory_footprint();
+        assert!(!store.symbols.get("RING").unwrap().has_quantiles());
+
+        // Act: The first query builds the tree, and later appends maintain it.
+        store.get_quantiles("RING", 10, &[0.5]).unwrap();
+        for batch in all_values[1200..].chunks(77) {
+            store.add_batch("RING", batch).unwrap();
+        }
+        let stats = store.get_quantiles("RING", 400, &[0.1, 0.5, 0.9]).unwrap();
+
+        // Assert
+        assert!(store.symbols.get("RING").unwrap().has_quantiles());
+        assert!(store.memory_footprint() > footprint);
+        for (q, value) in stats.quantiles {
+            fuzzy_assert_eq(value, brute_force_quantile(&all_values[1100..], q));
+        }
+    }
+
+    #[test]
+    fn test_quantile_tree_catches_up_with_appends_during_its_build() {
+        for retention in [None, Some(400)] {
+            // Arrange: Copy the slots, then keep appending while the tree is
+            // built. With retention, the later appends wrap around.
+            let mut data = SymbolData::new(retention, 64);
+            let all_values: Vec<f64> = (0..1500).map(|i| ((i * 37) % 101) as f64).collect();
+            let timestamps = [0; 77];
+            for batch in all_values[..1100].chunks(77) {
+                data.append(batch, &timestamps[..batch.len()]);
+            }
+            let source = data.quantile_source();
+            for batch in all_values[1100..].chunks(77) {
+                data.append(batch, &timestamps[..batch.len()]);
+            }
+
+            // Act
+            let tree = source.build();
+            assert!(data.install_quantiles(source, tree));
+
+            // Assert: Windows over old and new points match sorting them.
+            for window in [10, 350, 400] {
+                let (start, end) = (data.len() - window, data.len() - 1);
+                let mut sorted = all_values[start..].to_vec();
+                sorted.sort_by(f64::total_cmp);
+                for rank in [0, window / 2, window - 1] {
+                    assert_eq!(data.select(start, end, rank), sorted[rank]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantile_source_is_not_installed_after_a_reset() {
+        // Arrange: The symbol is reset after its slots were copied.
+        let store = Store::new();
+        store.add_batch("TEST", &[1.0, 2.0, 3.0]).unwrap();
+        let source = store.symbols.get("TEST").unwrap().quantile_source();
+        store.reset_symbol("TEST").unwrap();
+        store.add_batch("TEST", &[7.0, 8.0, 9.0]).unwrap();
+
+        // Act
+        let tree = source.build();
+        let installed = store
+            .symbols
+            .get_mut("TEST")
+            .unwrap()
+            .install_quantiles(source, tree);
+
+        // Assert: The stale tree is refused, and a query builds a fresh one.
+        assert!(!installed);
+        assert!(!store.symbols.get("TEST").unwrap().has_quantiles());
+        let stats = store.get_quantiles("TEST", 3, &[0.5]).unwrap();
+        assert_eq!(stats.quantiles, vec![(0.5, 8.0)]);
+    }
+
+    #[test]
+    fn test_approx_quantiles_over_ring_buffer() {
+        // Arrange: Retain 10_000 points, so windows span sketches, and wrap.
+        let store = store_with_retention(10_000);
+        let all_values: Vec<f64> = (0..23_333u64)
+            .map(|i| ((i * 7_919) % 10_007) as f64)
+            .collect();
+        for batch in all_values.chunks(1_777) {
+            store.add_batch("RING", batch).unwrap();
+        }
+
+        // Act & Assert: Each value is a point of the window whose rank is
+        // within the reported error of the requested one.
+        for window in [100, 5_000, 9_999, 10_000] {
+            let stats = store
+                .get_approx_quantiles("RING", window, &[0.1, 0.5, 0.9], 0.01)
+                .unwrap();
+            let rank_error = stats.rank_error.unwrap();
+            assert!(rank_error <= 0.01);
+
+            let expected_window = &all_values[23_333 - window..];
+            let n = window as f64;
+            for (q, value) in stats.quantiles {
+                let below = expected_window.iter().filter(|&&v| v < value).count() as f64;
+                let at_or_below = expected_window.iter().filter(|&&v| v <= value).count() as f64;
+                let target = (q * n).ceil();
+                assert!(at_or_below > 0.0);
+                assert!(below < target + rank_error * n);
+                assert!(at_or_below >= target - rank_error * n);
+            }
+        }
+    }
 }
@@ -0,0 +1,180 @@
+use crate::{config::LimitsConfig, AppError};
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// The most offending indices spelled out in an error message.
+const MAX_REPORTED_INDICES: usize = 20;
+
+/// What to do with a batch that contains invalid values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidValuePolicy {
+    /// Refuse the whole batch, reporting the index of every invalid value.
+    #[default]
+    Reject,
+    /// Keep the valid values (and their timestamps) and skip the rest.
+    Drop,
+}
+
+/// A batch that passed validation, borrowing the input unless values were dropped.
+#[derive(Debug)]
+pub struct ValidBatch<'a> {
+    pub values: Cow<'a, [f64]>,
+    /// `None` for a batch without timestamps, which the store stamps itself.
+    pub timestamps: Option<Cow<'a, [u64]>>,
+    /// The indices, within the original batch, of the values that were dropped.
+    pub dropped: Vec<usize>,
+}
+
+/// Whether a price may be stored. NaN and infinities would poison every tree
+/// node above them, so only finite, non-negative values are accepted.
+pub fn is_valid_value(value: f64) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+/// Checks a batch against the rules every ingestion path shares: it must be
+/// non-empty, within `limits.max_batch_size`, have one timestamp per value (if it
+/// has timestamps), and hold only valid values (see `is_valid_value`). Invalid
+/// values are handled according to `policy`; a batch whose values are all
+/// dropped is empty.
+pub fn validate_batch<'a>(
+    values: &'a [f64],
+    timestamps: Option<&'a [u64]>,
+    limits: &LimitsConfig,
+    policy: InvalidValuePolicy,
+) -> Result<ValidBatch<'a>, AppError> {
+    if values.is_empty() {
+        return Err(AppError::BadRequest(
+            "Cannot add an empty batch of values".to_string(),
+        ));
+    }
+    if values.len() > limits.max_batch_size {
+        return Err(AppError::BadRequest(format!(
+            "Batch size cannot exceed {} values.",
+            limits.max_batch_size
+        )));
+    }
+    if timestamps.is_some_and(|timestamps| timestamps.len() != values.len()) {
+        return Err(AppError::BadRequest(
+            "timestamps must have the same length as values".to_string(),
+        ));
+    }
+
+    let invalid: Vec<usize> = values
+        .iter()
+        .enumerate()
+        .filter(|&(_, &value)| !is_valid_value(value))
+        .map(|(i, _)| i)
+        .collect();
+    if invalid.is_empty() {
+        return Ok(ValidBatch {
+            values: Cow::Borrowed(values),
+            timestamps: timestamps.map(Cow::Borrowed),
+            dropped: invalid,
+        });
+    }
+
+    match policy {
+        InvalidValuePolicy::Reject => Err(AppError::InvalidValues { indices: invalid }),
+        InvalidValuePolicy::Drop => {
+            let timestamps = timestamps.map(|timestamps| {
+                timestamps
+                    .iter()
+                    .zip(values)
+                    .filter(|&(_, &value)| is_valid_value(value))
+                    .map(|(&timestamp, _)| timestamp)
+                    .collect()
+            });
+            Ok(ValidBatch {
+                values: Cow::Owned(
+                    values
+                        .iter()
+                        .copied()
+                        .filter(|&v| is_valid_value(v))
+                        .collect(),
+                ),
+                timestamps: timestamps.map(Cow::Owned),
+                dropped: invalid,
+            })
+        }
+    }
+}
+
+/// Lists indices for an error message, eliding all but the first few.
+pub(crate) fn format_indices(indices: &[usize]) -> String {
+    let listed: Vec<String> = indices
+        .iter()
+        .take(MAX_REPORTED_INDICES)
+        .map(usize::to_string)
+        .collect();
+    match indices.len().checked_sub(MAX_REPORTED_INDICES) {
+        Some(more) if more > 0 => format!("[{}, and {} more]", listed.join(", "), more),
+        _ => format!("[{}]", listed.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_batch_is_borrowed() {
+        let limits = LimitsConfig::default();
+        let batch = validate_batch(
+            &[1.0, 0.0, 2.5],
+            Some(&[1, 2, 3]),
+            &limits,
+            Default::default(),
+        )
+        .unwrap();
+        assert!(matches!(batch.values, Cow::Borrowed(_)));
+        assert!(batch.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected_or_dropped() {
+        // Arrange
+        let limits = LimitsConfig::default();
+        let values = [
+            1.0,
+            f64::NAN,
+            2.0,
+            -3.0,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            4.0,
+        ];
+        let timestamps = [10, 20, 30, 40, 50, 60, 70];
+
+        // Act
+        let rejected = validate_batch(
+            &values,
+            Some(&timestamps),
+            &limits,
+            InvalidValuePolicy::Reject,
+        );
+        let dropped = validate_batch(
+            &values,
+            Some(&timestamps),
+            &limits,
+            InvalidValuePolicy::Drop,
+        )
+        .unwrap();
+
+        // Assert
+        match rejected {
+            Err(AppError::InvalidValues { indices }) => assert_eq!(indices, vec![1, 3, 4, 5]),
+            other => panic!("Expected InvalidValues, got {:?}", other),
+        }
+        assert_eq!(dropped.values.as_ref(), &[1.0, 2.0, 4.0]);
+        assert_eq!(dropped.timestamps.as_deref(), Some(&[10, 30, 70][..]));
+        assert_eq!(dropped.dropped, vec![1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_format_indices_elides_long_lists() {
+        assert_eq!(format_indices(&[3, 7]), "[3, 7]");
+        let many: Vec<usize> = (0..25).collect();
+        assert!(format_indices(&many).ends_with("19, and 5 more]"));
+    }
+}
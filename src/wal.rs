@@ -0,0 +1,563 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Identifies a write-ahead log segment.
+const MAGIC: &[u8; 8] = b"HFTWAL\0\0";
+/// The current on-disk format version. Bump this whenever the layout changes.
+pub const WAL_VERSION: u32 = 1;
+/// The file extension of log segments.
+const SEGMENT_EXTENSION: &str = "wal";
+/// The length of a segment's magic and version.
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+/// Record kind for an appended batch.
+const RECORD_BATCH: u8 = 1;
+/// Record kind for a removed symbol.
+const RECORD_REMOVE: u8 = 2;
+/// Record kind for a symbol whose data was cleared.
+const RECORD_RESET: u8 = 3;
+/// Record kind for batches that were ingested together and must be replayed
+/// all or not at all.
+const RECORD_GROUP: u8 = 4;
+
+// The log is a directory of numbered segments (`00000000000000000001.wal`, ...).
+// A new segment is started on every open and every snapshot, and segments that
+// a snapshot already covers are deleted.
+//
+// Segment layout (all integers little-endian):
+//
+//   magic: [u8; 8]   version: u32
+//   records, each:
+//     payload_len: u32   checksum: u32 (CRC32 of payload)   payload
+//
+// Batch payload:
+//
+//   kind: u8 (= 1)   name_len: u32   name: [u8; name_len]
+//   start_index: u64   count: u64   values: [f64; count]   timestamps: [u64; count]
+//
+// Remove and reset payloads:
+//
+//   kind: u8 (= 2 or 3)   name_len: u32   name: [u8; name_len]
+//
+// Group payload:
+//
+//   kind: u8 (= 4)   batch_count: u32
+//   batches, each laid out like a batch payload without its kind
+
+#[derive(Debug, Error)]
+pub enum WalError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unsupported write-ahead log version {0} (expected {WAL_VERSION})")]
+    UnsupportedVersion(u32),
+    #[error(
+        "An earlier write or sync failed; the log accepts writes again after the next snapshot"
+    )]
+    Failed,
+}
+
+/// When appended records are flushed to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// `fsync` after every batch. No acknowledged batch is ever lost.
+    EveryBatch,
+    /// `fsync` at most once per interval. Up to one interval of batches can be
+    /// lost on power failure, but never on a process crash.
+    Interval(Duration),
+    /// Leave flushing to the operating system.
+    Never,
+}
+
+/// A single logged operation, as read back during replay.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalRecord {
+    Batch(BatchRecord),
+    /// Batches that were ingested atomically, in order.
+    Group(Vec<BatchRecord>),
+    /// The symbol was removed from the store.
+    Remove {
+        symbol: String,
+    },
+    /// The symbol's data was cleared, keeping the symbol itself.
+    Reset {
+        symbol: String,
+    },
+}
+
+/// A logged batch of values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchRecord {
+    pub symbol: String,
+    /// The absolute index of the first value in the batch.
+    pub start_index: u64,
+    pub values: Vec<f64>,
+    pub timestamps: Vec<u64>,
+}
+
+/// A short description of a replay.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplaySummary {
+    pub records: usize,
+    /// The number of bytes cut from torn or corrupt segment tails.
+    pub truncated_bytes: u64,
+}
+
+/// Identifies an appended record, so that its writer can wait for it to be
+/// synced with `Wal::commit`. Later records have greater sequence numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[must_use = "an appended record is only synced once it is committed"]
+pub struct Lsn(u64);
+
+/// An append-only, checksummed log of every batch added to the store.
+pub struct Wal {
+    dir: PathBuf,
+    policy: FsyncPolicy,
+    writer: Mutex<SegmentWriter>,
+    // Lets a single thread sync at a time; the ones waiting behind it usually
+    // find their records synced by it.
+    sync_lock: Mutex<()>,
+}
+
+struct SegmentWriter {
+    // Shared with a sync in progress, which runs without holding the writer.
+    file: Arc<File>,
+    segment: u64,
+    /// The length of the segment, up to the end of its last complete record.
+    len: u64,
+    /// The sequence number of the last appended record.
+    appended: u64,
+    /// Every record up to this sequence number is on stable storage.
+    synced: u64,
+    last_sync: Instant,
+    /// Set when a failed write could not be cut off the segment again, or a sync
+    /// failed. Appending after it would put records behind a torn or lost one,
+    /// so appends are refused until the next rotation.
+    failed: bool,
+}
+
+impl Wal {
+    /// Opens the log in `dir`, starting a fresh segment after any existing ones.
+    pub fn open(dir: impl Into<PathBuf>, policy: FsyncPolicy) -> Result<Self, WalError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let segment = list_segments(&dir)?.last().map_or(1, |&last| last + 1);
+        let file = create_segment(&dir, segment)?;
+
+        Ok(Self {
+            dir,
+            policy,
+            writer: Mutex::new(SegmentWriter {
+                file: Arc::new(file),
+                segment,
+                len: HEADER_LEN as u64,
+                appended: 0,
+                synced: 0,
+                last_sync: Instant::now(),
+                failed: false,
+            }),
+            sync_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Appends a batch record. It is synced by `commit`.
+    pub fn append_batch(
+        &self,
+        symbol: &str,
+        start_index: usize,
+        values: &[f64],
+        timestamps: &[u64],
+    ) -> Result<Lsn, WalError> {
+        let mut payload = Vec::with_capacity(1 + 4 + symbol.len() + 16 + values.len() * 16);
+        payload.push(RECORD_BATCH);
+        encode_batch(&mut payload, symbol, start_index, values, timestamps);
+        self.append_record(&payload)
+    }
+
+    /// Appends the `(symbol, start_index, values, timestamps)` batches as a
+    /// single record, so that replay applies either all of them or none. It is
+    /// synced by `commit`.
+    pub fn append_batches<'a>(
+        &self,
+        batches: impl IntoIterator<Item = (&'a str, usize, &'a [f64], &'a [u64])>,
+    ) -> Result<Lsn, WalError> {
+        let mut payload = vec![RECORD_GROUP];
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        let mut count: u32 = 0;
+        for (symbol, start_index, values, timestamps) in batches {
+            encode_batch(&mut payload, symbol, start_index, values, timestamps);
+            count += 1;
+        }
+        payload[1..5].copy_from_slice(&count.to_le_bytes());
+        self.append_record(&payload)
+    }
+
+    /// Appends a record of `symbol` being removed from the store.
+    pub fn append_remove(&self, symbol: &str) -> Result<Lsn, WalError> {
+        let mut payload = vec![RECORD_REMOVE];
+        encode_name(&mut payload, symbol);
+        self.append_record(&payload)
+    }
+
+    /// Appends a record of `symbol`'s data being cleared.
+    pub fn append_reset(&self, symbol: &str) -> Result<Lsn, WalError> {
+        let mut payload = vec![RECORD_RESET];
+        encode_name(&mut payload, symbol);
+        self.append_record(&payload)
+    }
+
+    /// Frames `payload` and writes it, returning its sequence number. If the
+    /// write fails, the record is cut off the segment again, so a batch that is
+    /// rejected is never replayed.
+    fn append_record(&self, payload: &[u8]) -> Result<Lsn, WalError> {
+        // Frame the record and write it with a single call.
+        let mut record = Vec::with_capacity(8 + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+        record.extend_from_slice(payload);
+
+        let mut writer = self.lock_writer();
+        if writer.failed {
+            return Err(WalError::Failed);
+        }
+
+        if let Err(e) = (&*writer.file).write_all(&record) {
+            // A partial write would leave a torn record that ends replay early,
+            // and a complete one a batch that was never applied.
+            if let Err(truncate_error) = writer.file.set_len(writer.len) {
+                warn!(
+                    error = %truncate_error,
+                    "Failed to truncate write-ahead log after a failed write"
+                );
+                writer.failed = true;
+            }
+            return Err(e.into());
+        }
+        writer.len += record.len() as u64;
+        writer.appended += 1;
+        Ok(Lsn(writer.appended))
+    }
+
+    /// Syncs the records up to `lsn` as the fsync policy requires. Call this
+    /// after releasing any lock that other writers wait on, since a sync takes a
+    /// disk round trip; records committed at the same time share a single sync.
+    ///
+    /// A failed sync may have lost records that were already applied, so the log
+    /// then refuses further appends until the next rotation.
+    pub fn commit(&self, lsn: Lsn) -> Result<(), WalError> {
+        match self.policy {
+            FsyncPolicy::EveryBatch => self.sync_through(lsn.0),
+            FsyncPolicy::Interval(interval)
+                if self.lock_writer().last_sync.elapsed() >= interval =>
+            {
+                self.sync_through(lsn.0)
+            }
+            FsyncPolicy::Interval(_) | FsyncPolicy::Never => Ok(()),
+        }
+    }
+
+    /// Flushes any unsynced records to stable storage.
+    pub fn sync(&self) -> Result<(), WalError> {
+        let appended = self.lock_writer().appended;
+        self.sync_through(appended)
+    }
+
+    /// Syncs the current segment, unless every record up to `target` already is.
+    fn sync_through(&self, target: u64) -> Result<(), WalError> {
+        let _sync = self
+            .sync_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let (file, segment, appended) = {
+            let writer = self.lock_writer();
+            if writer.synced >= target {
+                return Ok(());
+            }
+            (Arc::clone(&writer.file), writer.segment, writer.appended)
+        };
+
+        // Appends carry on while the file syncs, and are covered by the next sync.
+        if let Err(e) = file.sync_data() {
+            let mut writer = self.lock_writer();
+            // A rotation in the meantime synced the whole segment.
+            if writer.segment != segment {
+                return Ok(());
+            }
+            writer.failed = true;
+            return Err(e.into());
+        }
+        let mut writer = self.lock_writer();
+        writer.synced = writer.synced.max(appended);
+        writer.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Starts a new segment and returns its number. Every record in earlier
+    /// segments was written before this call returned.
+    pub fn rotate(&self) -> Result<u64, WalError> {
+        let mut writer = self.lock_writer();
+        writer.file.sync_data()?;
+
+        let segment = writer.segment + 1;
+        writer.file = Arc::new(create_segment(&self.dir, segment)?);
+        writer.segment = segment;
+        writer.len = HEADER_LEN as u64;
+        writer.synced = writer.appended;
+        writer.last_sync = Instant::now();
+        writer.failed = false;
+        Ok(segment)
+    }
+
+    /// Deletes every segment numbered below `segment`.
+    pub fn remove_segments_before(&self, segment: u64) -> Result<(), WalError> {
+        for old in list_segments(&self.dir)?
+            .into_iter()
+            .filter(|&s| s < segment)
+        {
+            fs::remove_file(segment_path(&self.dir, old))?;
+        }
+        Ok(())
+    }
+
+    /// Reads every record from every segment, oldest first, and passes it to
+    /// `apply`. A segment ends at its first torn or corrupt record, which is
+    /// truncated away so that the log is consistent again.
+    pub fn replay(&self, mut apply: impl FnMut(WalRecord)) -> Result<ReplaySummary, WalError> {
+        let current = self.lock_writer().segment;
+        let mut summary = ReplaySummary::default();
+
+        for segment in list_segments(&self.dir)?
+            .into_iter()
+            .filter(|&s| s < current)
+        {
+            let path = segment_path(&self.dir, segment);
+            let bytes = fs::read(&path)?;
+
+            let (records, valid_len) = decode_segment(&bytes)?;
+            for record in records {
+                apply(record);
+                summary.records += 1;
+            }
+
+            if valid_len < bytes.len() {
+                let truncated = (bytes.len() - valid_len) as u64;
+                warn!(
+                    path = %path.display(),
+                    truncated_bytes = truncated,
+                    "Truncating torn write-ahead log tail"
+                );
+                let file = OpenOptions::new().write(true).open(&path)?;
+                file.set_len(valid_len as u64)?;
+                file.sync_all()?;
+                summary.truncated_bytes += truncated;
+            }
+        }
+
+        info!(
+            records = summary.records,
+            truncated_bytes = summary.truncated_bytes,
+            "Write-ahead log replayed"
+        );
+        Ok(summary)
+    }
+
+    fn lock_writer(&self) -> std::sync::MutexGuard<'_, SegmentWriter> {
+        self.writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Writes the fields of a batch, everything after its kind.
+fn encode_batch(
+    payload: &mut Vec<u8>,
+    symbol: &str,
+    start_index: usize,
+    values: &[f64],
+    timestamps: &[u64],
+) {
+    encode_name(payload, symbol);
+    payload.extend_from_slice(&(start_index as u64).to_le_bytes());
+    payload.extend_from_slice(&(values.len() as u64).to_le_bytes());
+    for value in values {
+        payload.extend_from_slice(&value.to_le_bytes());
+    }
+    for timestamp in timestamps {
+        payload.extend_from_slice(&timestamp.to_le_bytes());
+    }
+}
+
+/// Writes a length-prefixed symbol name.
+fn encode_name(payload: &mut Vec<u8>, symbol: &str) {
+    payload.extend_from_slice(&(symbol.len() as u32).to_le_bytes());
+    payload.extend_from_slice(symbol.as_bytes());
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", segment, SEGMENT_EXTENSION))
+}
+
+/// Returns the numbers of all segments in `dir`, in ascending order.
+fn list_segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(segment) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            segments.push(segment);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+fn create_segment(dir: &Path, segment: u64) -> io::Result<File> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(dir, segment))?;
+    file.write_all(MAGIC)?;
+    file.write_all(&WAL_VERSION.to_le_bytes())?;
+    file.sync_all()?;
+
+    // Make the new directory entry itself durable.
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(file)
+}
+
+/// Decodes all intact records of a segment. Returns them along with the length
+/// of the valid prefix of `bytes`; anything after it is a torn or corrupt tail.
+fn decode_segment(bytes: &[u8]) -> Result<(Vec<WalRecord>, usize), WalError> {
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        // A segment whose header never made it to disk holds no records.
+        return Ok((Vec::new(), 0));
+    }
+    let version = u32::from_le_bytes(bytes[MAGIC.len()..HEADER_LEN].try_into().unwrap());
+    if version != WAL_VERSION {
+        return Err(WalError::UnsupportedVersion(version));
+    }
+
+    let mut records = Vec::new();
+    let mut offset = HEADER_LEN;
+    while let Some((record, next)) = decode_record(bytes, offset) {
+        records.push(record);
+        offset = next;
+    }
+    Ok((records, offset))
+}
+
+/// Decodes the record starting at `offset`, returning it and the offset of the
+/// next record, or `None` if it is incomplete or fails its checksum.
+fn decode_record(bytes: &[u8], offset: usize) -> Option<(WalRecord, usize)> {
+    let mut frame = bytes.get(offset..)?;
+    let payload_len = read_u32(&mut frame).ok()? as usize;
+    let checksum = read_u32(&mut frame).ok()?;
+    let payload = frame.get(..payload_len)?;
+    if crc32fast::hash(payload) != checksum {
+        return None;
+    }
+
+    let record = decode_payload(payload).ok()?;
+    Some((record, offset + 8 + payload_len))
+}
+
+fn decode_payload(mut payload: &[u8]) -> io::Result<WalRecord> {
+    let mut kind = [0u8; 1];
+    payload.read_exact(&mut kind)?;
+
+    let record = match kind[0] {
+        RECORD_BATCH => WalRecord::Batch(decode_batch(&mut payload)?),
+        RECORD_REMOVE => WalRecord::Remove {
+            symbol: decode_name(&mut payload)?,
+        },
+        RECORD_RESET => WalRecord::Reset {
+            symbol: decode_name(&mut payload)?,
+        },
+        RECORD_GROUP => {
+            let count = read_u32(&mut payload)?;
+            let batches = (0..count)
+                .map(|_| decode_batch(&mut payload))
+                .collect::<io::Result<Vec<_>>>()?;
+            WalRecord::Group(batches)
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown record kind",
+            ))
+        }
+    };
+    if !payload.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected data at the end of a record",
+        ));
+    }
+    Ok(record)
+}
+
+/// Decodes a length-prefixed symbol name.
+fn decode_name(payload: &mut &[u8]) -> io::Result<String> {
+    let name_len = read_u32(payload)? as usize;
+    let name = payload
+        .get(..name_len)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "symbol name is truncated"))?;
+    *payload = &payload[name_len..];
+    String::from_utf8(name.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Decodes the fields of a batch, everything after its kind.
+fn decode_batch(payload: &mut &[u8]) -> io::Result<BatchRecord> {
+    let symbol = decode_name(payload)?;
+    let start_index = read_u64(payload)?;
+    let count = read_u64(payload)? as usize;
+    if count.checked_mul(16).is_none_or(|len| len > payload.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "record length does not match its value count",
+        ));
+    }
+    let values = (0..count)
+        .map(|_| read_u64(payload).map(f64::from_bits))
+        .collect::<io::Result<Vec<_>>>()?;
+    let timestamps = (0..count)
+        .map(|_| read_u64(payload))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(BatchRecord {
+        symbol,
+        start_index,
+        values,
+        timestamps,
+    })
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
@@ -0,0 +1,380 @@
+use crate::{
+    apply_batch, binary, resolve_ddof, resolve_window_size, store::Store, AddBatchRequest,
+    AppError, BatchStatsOutcome, BatchStatsQuery, IngestItemResponse, SharedState,
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tracing::{info, instrument, warn};
+
+/// The most `(symbol, window)` pairs a single `/ws/stats` connection may follow.
+const MAX_SUBSCRIPTIONS: usize = 100;
+/// The longest throttle interval a `/ws/stats` client may ask for.
+const MAX_THROTTLE_MS: u64 = 60_000;
+
+/// A message from a `/ws/stats` client. Every field is optional, so a single
+/// message can change the subscriptions and the throttle interval together.
+#[derive(Debug, Deserialize)]
+struct StatsCommand {
+    /// Windows to follow, each pushed once straight away and then whenever
+    /// its symbol changes.
+    #[serde(default)]
+    subscribe: Vec<BatchStatsQuery>,
+    /// Symbols to stop following, with all of their windows.
+    #[serde(default)]
+    unsubscribe: Vec<String>,
+    /// If non-zero, changes are coalesced and pushed at most once per this many
+    /// milliseconds, instead of after every batch.
+    throttle_ms: Option<u64>,
+}
+
+/// One window a client follows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Subscription {
+    pub(crate) symbol: String,
+    pub(crate) window: usize,
+    pub(crate) ddof: u32,
+}
+
+impl Subscription {
+    /// The current statistics of the window, or the error computing them
+    /// produced, such as an unknown symbol.
+    pub(crate) fn frame(&self, store: &Store) -> StatsFrame<'_> {
+        let stats = store
+            .get_stats(&self.symbol, self.window)
+            .and_then(|stats| stats.with_ddof(self.ddof));
+        StatsFrame {
+            symbol: &self.symbol,
+            window: self.window,
+            outcome: stats.into(),
+        }
+    }
+}
+
+/// A statistics snapshot pushed to a client.
+#[derive(Serialize)]
+pub(crate) struct StatsFrame<'a> {
+    symbol: &'a str,
+    window: usize,
+    #[serde(flatten)]
+    outcome: BatchStatsOutcome,
+}
+
+/// The acknowledgement of one frame sent to `/ws/ingest`.
+#[derive(Serialize)]
+struct IngestAck {
+    /// The position of the frame on the connection, counting from 0.
+    seq: u64,
+    #[serde(flatten)]
+    outcome: IngestOutcome,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum IngestOutcome {
+    Batch(IngestItemResponse),
+    /// A frame that could not be read as a batch at all.
+    Malformed {
+        status: u16,
+        error: String,
+    },
+}
+
+/// What woke up a `/ws/stats` connection.
+enum Event {
+    Message(Option<Result<Message, axum::Error>>),
+    Update(Result<Arc<str>, RecvError>),
+    Tick,
+    Closed,
+}
+
+#[instrument(name = "stats_ws_request", skip_all)]
+pub(crate) async fn stats_ws_handler(
+    State(state): State<SharedState>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| stream_stats(socket, state))
+}
+
+/// Serves one `/ws/stats` connection until the client goes away or the
+/// server shuts down.
+async fn stream_stats(mut socket: WebSocket, state: SharedState) {
+    // Subscribe before reading any commands, so no change made after a
+    // subscription's first push can be missed.
+    let mut updates = state.subscribe();
+    let closed = state.streams_closed();
+    tokio::pin!(closed);
+    let mut session = StatsSession {
+        state,
+        subscriptions: Vec::new(),
+        changed: HashSet::new(),
+        throttle: None,
+    };
+    info!("Stats stream opened");
+
+    loop {
+        let event = tokio::select! {
+            message = socket.recv() => Event::Message(message),
+            update = updates.recv() => Event::Update(update),
+            _ = next_tick(&mut session.throttle) => Event::Tick,
+            _ = &mut closed => Event::Closed,
+        };
+
+        let sent = match event {
+            Event::Message(Some(Ok(Message::Text(text)))) => {
+                session.handle_command(&mut socket, &text).await
+            }
+            Event::Message(Some(Ok(Message::Close(_)))) | Event::Message(Some(Err(_))) => break,
+            Event::Message(None) => break,
+            // Pings are answered by axum itself; other frames carry no commands.
+            Event::Message(Some(Ok(_))) => Ok(()),
+            Event::Update(Ok(symbol)) => {
+                session.mark_changed(&symbol);
+                session.push_unless_throttled(&mut socket).await
+            }
+            Event::Update(Err(RecvError::Lagged(missed))) => {
+                // Some notifications were discarded, so any symbol may have changed.
+                warn!(missed, "Stats stream fell behind the store");
+                session.mark_all_changed();
+                session.push_unless_throttled(&mut socket).await
+            }
+            Event::Update(Err(RecvError::Closed)) => break,
+            Event::Tick => session.push_changed(&mut socket).await,
+            Event::Closed => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+        };
+        if sent.is_err() {
+            break;
+        }
+    }
+
+    info!("Stats stream closed");
+}
+
+/// Waits for the next throttle tick, or forever if pushes are not throttled.
+async fn next_tick(throttle: &mut Option<Interval>) {
+    match throttle {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The state of one `/ws/stats` connection.
+struct StatsSession {
+    state: SharedState,
+    subscriptions: Vec<Subscription>,
+    /// Followed symbols that changed since their windows were last pushed.
+    changed: HashSet<String>,
+    throttle: Option<Interval>,
+}
+
+impl StatsSession {
+    /// Applies a client command, replying with an error frame if it is invalid.
+    /// An invalid command changes nothing.
+    async fn handle_command(
+        &mut self,
+        socket: &mut WebSocket,
+        text: &str,
+    ) -> Result<(), axum::Error> {
+        match self.apply_command(text) {
+            Ok(added) => self.push(socket, &added).await,
+            Err(err) => {
+                let (status, error) = err.status_and_message();
+                let body = serde_json::json!({ "error": error, "status": status.as_u16() });
+                socket.send(Message::Text(body.to_string().into())).await
+            }
+        }
+    }
+
+    /// Updates the subscriptions and throttle, returning the subscriptions the
+    /// command asked for so that their current statistics can be pushed.
+    fn apply_command(&mut self, text: &str) -> Result<Vec<Subscription>, AppError> {
+        let command: StatsCommand = serde_json::from_str(text)
+            .map_err(|e| AppError::BadRequest(format!("Invalid command: {}", e)))?;
+
+        let requested = command
+            .subscribe
+            .into_iter()
+            .map(|query| {
+                Ok(Subscription {
+                    window: resolve_window_size(self.state.limits(), query.exponent, query.window)?,
+                    ddof: resolve_ddof(query.ddof)?,
+                    symbol: query.symbol,
+                })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+        if let Some(throttle_ms) = command.throttle_ms {
+            if throttle_ms > MAX_THROTTLE_MS {
+                return Err(AppError::BadRequest(format!(
+                    "throttle_ms must be at most {}",
+                    MAX_THROTTLE_MS
+                )));
+            }
+        }
+
+        let mut subscriptions: Vec<Subscription> = self
+            .subscriptions
+            .iter()
+            .filter(|s| !command.unsubscribe.contains(&s.symbol))
+            .cloned()
+            .collect();
+        for subscription in &requested {
+            if !subscriptions.contains(subscription) {
+                subscriptions.push(subscription.clone());
+            }
+        }
+        if subscriptions.len() > MAX_SUBSCRIPTIONS {
+            return Err(AppError::BadRequest(format!(
+                "At most {} windows may be followed at once",
+                MAX_SUBSCRIPTIONS
+            )));
+        }
+
+        self.subscriptions = subscriptions;
+        self.changed
+            .retain(|symbol| !command.unsubscribe.contains(symbol));
+        if let Some(throttle_ms) = command.throttle_ms {
+            self.throttle = (throttle_ms > 0).then(|| {
+                let period = Duration::from_millis(throttle_ms);
+                let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                interval
+            });
+        }
+        Ok(requested)
+    }
+
+    fn mark_changed(&mut self, symbol: &str) {
+        if self.subscriptions.iter().any(|s| s.symbol == symbol) && !self.changed.contains(symbol) {
+            self.changed.insert(symbol.to_string());
+        }
+    }
+
+    fn mark_all_changed(&mut self) {
+        self.changed
+            .extend(self.subscriptions.iter().map(|s| s.symbol.clone()));
+    }
+
+    async fn push_unless_throttled(&mut self, socket: &mut WebSocket) -> Result<(), axum::Error> {
+        if self.throttle.is_some() {
+            return Ok(());
+        }
+        self.push_changed(socket).await
+    }
+
+    /// Pushes every window of the symbols that changed since the last push.
+    async fn push_changed(&mut self, socket: &mut WebSocket) -> Result<(), axum::Error> {
+        if self.changed.is_empty() {
+            return Ok(());
+        }
+        let changed = std::mem::take(&mut self.changed);
+        let due: Vec<Subscription> = self
+            .subscriptions
+            .iter()
+            .filter(|s| changed.contains(&s.symbol))
+            .cloned()
+            .collect();
+        self.push(socket, &due).await
+    }
+
+    /// Sends the current statistics of each subscription.
+    async fn push(
+        &self,
+        socket: &mut WebSocket,
+        subscriptions: &[Subscription],
+    ) -> Result<(), axum::Error> {
+        for subscription in subscriptions {
+            let frame = subscription.frame(&self.state);
+            let text = serde_json::to_string(&frame).map_err(axum::Error::new)?;
+            socket.send(Message::Text(text.into())).await?;
+        }
+        Ok(())
+    }
+}
+
+#[instrument(name = "ingest_ws_request", skip_all)]
+pub(crate) async fn ingest_ws_handler(
+    State(state): State<SharedState>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| ingest_stream(socket, state))
+}
+
+/// Serves one `/ws/ingest` connection until the client goes away or the
+/// server shuts down.
+///
+/// Frames are applied and acknowledged one at a time, and the next frame is
+/// only read once the previous one has been acknowledged. A client that sends
+/// faster than the store can keep up is therefore held back by TCP flow
+/// control, instead of unread frames piling up in memory.
+async fn ingest_stream(mut socket: WebSocket, state: SharedState) {
+    info!("Ingest stream opened");
+    let (mut seq, mut applied) = (0, 0);
+    let closed = state.streams_closed();
+    tokio::pin!(closed);
+
+    loop {
+        // A frame that has been read is always applied and acknowledged.
+        let message = tokio::select! {
+            message = socket.recv() => message,
+            _ = &mut closed => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+        };
+        let Some(Ok(message)) = message else {
+            break;
+        };
+        // Text frames hold JSON batches and binary frames binary ones, both
+        // exactly as for `/add_batch/`.
+        let payload = match message {
+            Message::Text(text) => serde_json::from_str::<AddBatchRequest>(&text)
+                .map_err(|e| AppError::BadRequest(format!("Invalid batch: {}", e))),
+            Message::Binary(bytes) => binary::decode_batch(&bytes).map(AddBatchRequest::from),
+            Message::Close(_) => break,
+            // Pings are answered by axum itself.
+            Message::Ping(_) | Message::Pong(_) => continue,
+        };
+        let outcome = match payload {
+            Ok(payload) => {
+                let result = apply_batch(&state, &payload);
+                if result.is_ok() {
+                    applied += 1;
+                }
+                IngestOutcome::Batch(IngestItemResponse::new(payload.symbol, result))
+            }
+            Err(err) => {
+                let (status, error) = err.status_and_message();
+                IngestOutcome::Malformed {
+                    status: status.as_u16(),
+                    error,
+                }
+            }
+        };
+
+        let ack = IngestAck { seq, outcome };
+        seq += 1;
+        let Ok(text) = serde_json::to_string(&ack) else {
+            break;
+        };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+
+    info!(frames = seq, applied, "Ingest stream closed");
+}
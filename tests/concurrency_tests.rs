@@ -354,3 +354,338 @@ async fn test_memory_stability_under_load() {
 
     // If we get here without panicking or running out of memory, the test passes
 }
+
+/// Test that deleting a symbol frees its slot towards the symbol limit
+#[tokio::test]
+async fn test_delete_frees_symbol_slot() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+
+    for i in 1..=10 {
+        let request_body = json!({ "symbol": format!("SLOT-SYM-{:02}", i), "values": [1.0] });
+        let request = Request::builder()
+            .uri("/add_batch/")
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Delete a symbol created by mistake
+    let request = Request::builder()
+        .uri("/symbols/SLOT-SYM-03")
+        .method("DELETE")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Its data is gone, and deleting it again reports it as missing
+    let request = Request::builder()
+        .uri("/stats/?symbol=SLOT-SYM-03&exponent=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = Request::builder()
+        .uri("/symbols/SLOT-SYM-03")
+        .method("DELETE")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // The freed slot can be used by a new symbol
+    let request_body = json!({ "symbol": "SLOT-SYM-11", "values": [1.0] });
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+/// Test that a reset clears a symbol's data and timestamp history but keeps the symbol
+#[tokio::test]
+async fn test_reset_symbol() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+
+    let request_body =
+        json!({ "symbol": "RESET-SYM", "values": [1.0, 2.0], "timestamps": [5000, 6000] });
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder()
+        .uri("/symbols/RESET-SYM/reset")
+        .method("POST")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The symbol still exists but has no data
+    let request = Request::builder()
+        .uri("/stats/?symbol=RESET-SYM&exponent=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(state.symbols.len(), 1);
+
+    // Earlier timestamps are accepted again, starting from index 0
+    let request_body = json!({ "symbol": "RESET-SYM", "values": [7.0], "timestamps": [1000] });
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder()
+        .uri("/range_stats/?symbol=RESET-SYM&start=0&end=0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stats: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["min"], 7.0);
+
+    // Resetting an unknown symbol reports it as missing
+    let request = Request::builder()
+        .uri("/symbols/NO-SUCH-SYM/reset")
+        .method("POST")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+/// Test that deletes and resets racing with writers on the same symbol never
+/// split a batch: every batch is applied entirely before or entirely after them
+#[tokio::test]
+async fn test_concurrent_add_with_delete_and_reset() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+    let symbol = "RACE-SYM";
+    let batch_size = 50;
+
+    let mut handles = Vec::new();
+
+    // Spawn writers
+    for i in 0..4 {
+        let app_clone = app.clone();
+        let handle = tokio::spawn(async move {
+            for j in 0..25 {
+                let values: Vec<f64> = (0..batch_size).map(|k| (i * 10 + j + k) as f64).collect();
+                let request_body = json!({ "symbol": symbol, "values": values });
+
+                let request = Request::builder()
+                    .uri("/add_batch/")
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+                    .unwrap();
+
+                let response = app_clone.clone().oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        });
+        handles.push(handle);
+    }
+
+    // Spawn a task that alternately deletes and resets the symbol
+    let app_clone = app.clone();
+    let state_clone = state.clone();
+    handles.push(tokio::spawn(async move {
+        for j in 0..40 {
+            let request = if j % 2 == 0 {
+                Request::builder()
+                    .uri(format!("/symbols/{}", symbol))
+                    .method("DELETE")
+                    .body(Body::empty())
+                    .unwrap()
+            } else {
+                Request::builder()
+                    .uri(format!("/symbols/{}/reset", symbol))
+                    .method("POST")
+                    .body(Body::empty())
+                    .unwrap()
+            };
+
+            // The symbol may not exist at this moment
+            let response = app_clone.clone().oneshot(request).await.unwrap();
+            assert!(
+                response.status() == StatusCode::OK || response.status() == StatusCode::NOT_FOUND,
+                "Unexpected status {}",
+                response.status()
+            );
+
+            // Whatever is stored is always made of whole batches
+            if let Some(data) = state_clone.symbols.get(symbol) {
+                assert_eq!(data.len() % batch_size, 0);
+            }
+
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }));
+
+    for handle in futures::future::join_all(handles).await {
+        handle.unwrap();
+    }
+
+    let final_len = state.symbols.get(symbol).map_or(0, |data| data.len());
+    assert_eq!(final_len % batch_size, 0);
+    assert!(final_len <= 4 * 25 * batch_size);
+}
+
+/// Test that every window of a multi-window request is computed over the same
+/// version of the data, even while batches land in between
+#[tokio::test]
+async fn test_multi_window_stats_are_consistent_under_writes() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+    let symbol = "MULTI-SYM";
+    state.add_batch(symbol, &[1.0; 100]).unwrap();
+
+    // Spawn writers
+    let mut handles = Vec::new();
+    for i in 0..4 {
+        let app_clone = app.clone();
+        handles.push(tokio::spawn(async move {
+            for j in 0..50 {
+                let values: Vec<f64> = (0..20).map(|k| (i * 100 + j + k) as f64).collect();
+                let request_body = json!({ "symbol": symbol, "values": values });
+
+                let request = Request::builder()
+                    .uri("/add_batch/")
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+                    .unwrap();
+
+                let response = app_clone.clone().oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        }));
+    }
+
+    // Spawn a reader asking for several windows at once
+    let app_clone = app.clone();
+    handles.push(tokio::spawn(async move {
+        for _ in 0..100 {
+            let request = Request::builder()
+                .uri(format!(
+                    "/stats/?symbol={}&windows=10,100,1000,100000",
+                    symbol
+                ))
+                .body(Body::empty())
+                .unwrap();
+            let response = app_clone.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            let windows = body["windows"].as_array().unwrap();
+            assert_eq!(windows.len(), 4);
+
+            // All windows end at the same point, and only the largest is short
+            let end_index = &windows[0]["end_index"];
+            let total = end_index.as_u64().unwrap() + 1;
+            for window in windows {
+                assert_eq!(&window["end_index"], end_index);
+            }
+            assert_eq!(windows[2]["count"], 1000.min(total));
+            assert_eq!(windows[3]["count"], total);
+            assert_eq!(windows[3]["truncated"], true);
+
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }));
+
+    for handle in futures::future::join_all(handles).await {
+        handle.unwrap();
+    }
+}
+
+/// Test that an atomic multi-symbol ingestion is never partly applied, even
+/// while another writer adds later timestamps to one of its symbols
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_atomic_ingestion_under_concurrent_writes() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+
+    let post = |app: axum::Router, uri: &'static str, body: Value| async move {
+        let request = Request::builder()
+            .uri(uri)
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice::<Value>(&body).unwrap())
+    };
+
+    // Every atomic request adds to both "PAIRED" and "SHARED"; the other writer
+    // only to "SHARED", so either may find its timestamps already overtaken.
+    let app_clone = app.clone();
+    let atomic = tokio::spawn(async move {
+        let mut applied = 0;
+        for i in 0..200u64 {
+            let body = json!({
+                "batches": [
+                    { "symbol": "PAIRED", "values": [1.0], "timestamps": [i * 10] },
+                    { "symbol": "SHARED", "values": [1.0], "timestamps": [i * 10] }
+                ]
+            });
+            let (status, body) = post(app_clone.clone(), "/add_batches/", body).await;
+            match status {
+                StatusCode::OK => {
+                    assert_eq!(body["status"], "success");
+                    applied += 1;
+                }
+                StatusCode::BAD_REQUEST => {
+                    assert!(body["error"].as_str().unwrap().starts_with("batches[1]"))
+                }
+                other => panic!("Unexpected status {}: {}", other, body),
+            }
+        }
+        applied
+    });
+    let app_clone = app.clone();
+    let single = tokio::spawn(async move {
+        let mut applied = 0;
+        for i in 0..200u64 {
+            let body = json!({ "symbol": "SHARED", "values": [2.0], "timestamps": [i * 10 + 5] });
+            let (status, _) = post(app_clone.clone(), "/add_batch/", body).await;
+            if status == StatusCode::OK {
+                applied += 1;
+            }
+        }
+        applied
+    });
+
+    let (atomic, single) = (atomic.await.unwrap(), single.await.unwrap());
+    let paired = state.symbols.get("PAIRED").map_or(0, |data| data.len());
+    let shared = state.symbols.get("SHARED").map_or(0, |data| data.len());
+    assert_eq!(paired, atomic);
+    assert_eq!(shared, atomic + single);
+}
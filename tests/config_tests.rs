@@ -51,3 +51,52 @@ fn test_config_structure() {
         config.log.level
     );
 }
+
+#[test]
+fn test_default_retention_is_unbounded() {
+    let config = Config::new().expect("Should load config");
+
+    // The default Config.toml keeps every point for every symbol.
+    assert_eq!(config.retention.max_points, 0);
+    assert_eq!(config.retention.limit_for("ANY-SYMBOL"), None);
+}
+
+#[test]
+fn test_default_limits() {
+    let config = Config::new().expect("Should load config");
+
+    // The default Config.toml keeps the service's historical limits.
+    assert_eq!(config.limits.max_batch_size, 10_000);
+    assert_eq!(config.limits.max_symbols, 10);
+    assert_eq!(config.limits.min_exponent, 1);
+    assert_eq!(config.limits.max_exponent, 8);
+    assert_eq!(config.limits.max_window, 100_000_000);
+}
+
+#[test]
+fn test_limits_overridden_from_environment() {
+    // A dedicated prefix keeps this test from affecting the others.
+    std::env::set_var("HFT_LIMITS_TEST_LIMITS__MAX_SYMBOLS", "250");
+    std::env::set_var("HFT_LIMITS_TEST_LIMITS__MAX_BATCH_SIZE", "50000");
+
+    let config = Config::load("Config.toml", "HFT_LIMITS_TEST_").expect("Should load config");
+
+    assert_eq!(config.limits.max_symbols, 250);
+    assert_eq!(config.limits.max_batch_size, 50_000);
+    // Limits that were not overridden keep their Config.toml values.
+    assert_eq!(config.limits.max_exponent, 8);
+}
+
+#[test]
+fn test_wal_without_snapshots_is_rejected() {
+    // A dedicated prefix keeps this test from affecting the others.
+    std::env::set_var("HFT_WAL_TEST_WAL__ENABLED", "true");
+
+    let error = Config::load("Config.toml", "HFT_WAL_TEST_").unwrap_err();
+    assert!(error.to_string().contains("[snapshot] enabled"));
+
+    // With snapshots deleting the segments they cover, the log is accepted.
+    std::env::set_var("HFT_WAL_TEST_SNAPSHOT__ENABLED", "true");
+    let config = Config::load("Config.toml", "HFT_WAL_TEST_").expect("Should load config");
+    assert!(config.wal.enabled && config.snapshot.enabled);
+}
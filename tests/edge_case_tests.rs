@@ -1,4 +1,6 @@
-use hft_service::{app_router, store::Store, SharedState};
+use hft_service::{
+    app_router, store::Store, validation::InvalidValuePolicy, AppError, SharedState,
+};
 
 use axum::body::{to_bytes, Body};
 use axum::http::{Request, StatusCode};
@@ -92,6 +94,101 @@ async fn test_infinite_and_nan_rejection() {
     );
 }
 
+/// Test that the library API rejects NaN and infinities that JSON cannot carry,
+/// reporting every offending index and leaving the symbol untouched
+#[test]
+fn test_store_rejects_non_finite_values() {
+    let store = Store::new();
+    store.add_batch("NAN-TEST", &[100.0, 200.0]).unwrap();
+
+    let result = store.add_batch("NAN-TEST", &[1.0, f64::NAN, 2.0, f64::INFINITY, -1.0]);
+    match result {
+        Err(AppError::InvalidValues { indices }) => assert_eq!(indices, vec![1, 3, 4]),
+        other => panic!("Expected InvalidValues, got {:?}", other),
+    }
+
+    // The earlier data is not poisoned.
+    let stats = store.get_stats("NAN-TEST", 10).unwrap();
+    assert_eq!(stats.count, 2);
+    assert_eq!(stats.avg, 150.0);
+
+    // A batch made only of invalid values does not create its symbol.
+    assert!(store.add_batch("INF-TEST", &[f64::NEG_INFINITY]).is_err());
+    assert!(!store.symbols.contains_key("INF-TEST"));
+}
+
+/// Test that the drop policy stores the valid values with their own timestamps
+#[test]
+fn test_store_drops_non_finite_values() {
+    let store = Store::new();
+
+    let dropped = store
+        .add_batch_with_policy(
+            "DROP-TEST",
+            &[1.0, f64::NAN, 3.0, f64::INFINITY],
+            &[1_000, 2_000, 3_000, 4_000],
+            InvalidValuePolicy::Drop,
+        )
+        .unwrap();
+    assert_eq!(dropped, vec![1, 3]);
+
+    let stats = store.get_stats("DROP-TEST", 10).unwrap();
+    assert_eq!(stats.count, 2);
+    assert_eq!((stats.min, stats.max), (1.0, 3.0));
+    let stats = store.get_stats_between("DROP-TEST", 2_500, 4_000).unwrap();
+    assert_eq!(stats.count, 1);
+
+    // Dropping every value adds nothing, and is not an error.
+    let dropped = store
+        .add_batch_with_policy("DROP-TEST", &[f64::NAN], &[5_000], InvalidValuePolicy::Drop)
+        .unwrap();
+    assert_eq!(dropped, vec![0]);
+    assert_eq!(store.get_stats("DROP-TEST", 10).unwrap().count, 2);
+}
+
+/// Test that the store enforces the batch size limit, not only the HTTP handler
+#[test]
+fn test_store_enforces_batch_size_limit() {
+    let store = Store::new();
+    let max_batch_size = store.limits().max_batch_size;
+
+    assert!(store.add_batch("BIG", &vec![1.0; max_batch_size]).is_ok());
+    assert!(matches!(
+        store.add_batch("BIG", &vec![1.0; max_batch_size + 1]),
+        Err(AppError::BadRequest(_))
+    ));
+    assert!(matches!(
+        store.add_batch("BIG", &[]),
+        Err(AppError::BadRequest(_))
+    ));
+}
+
+/// Test that negative values can be dropped over HTTP, with the dropped indices reported
+#[tokio::test]
+async fn test_drop_policy_over_http() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+
+    let request_body = json!({
+        "symbol": "DROP-HTTP",
+        "values": [10.0, -1.0, 20.0, -2.0],
+        "on_invalid": "drop"
+    });
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body, json!({ "status": "success", "dropped": [1, 3] }));
+    assert_eq!(state.get_stats("DROP-HTTP", 10).unwrap().avg, 15.0);
+}
+
 /// Test with exact batch size limits
 #[tokio::test]
 async fn test_exact_batch_size_limits() {
@@ -156,6 +253,8 @@ async fn test_single_value_batches() {
     assert_eq!(stats["last"].as_f64().unwrap(), 42.5);
     assert_eq!(stats["avg"].as_f64().unwrap(), 42.5);
     assert_eq!(stats["var"].as_f64().unwrap(), 0.0); // Variance of single value is 0
+    assert_eq!(stats["skewness"].as_f64().unwrap(), 0.0);
+    assert_eq!(stats["kurtosis"].as_f64().unwrap(), 0.0);
 }
 
 /// Test with zero values (should be rejected)
@@ -255,6 +354,124 @@ async fn test_exponent_boundaries() {
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
 
+/// Test window boundary values
+#[tokio::test]
+async fn test_window_boundaries() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+
+    // Add some test data first
+    let request_body = json!({ "symbol": "WIN-TEST", "values": (1..=100).map(|i| i as f64).collect::<Vec<f64>>() });
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Test window = 1 (minimum valid)
+    let stats_request = Request::builder()
+        .uri("/stats/?symbol=WIN-TEST&window=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(stats_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Test window = 100000000 (maximum valid)
+    let stats_request = Request::builder()
+        .uri("/stats/?symbol=WIN-TEST&window=100000000")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(stats_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Test window = 0 (should fail)
+    let stats_request = Request::builder()
+        .uri("/stats/?symbol=WIN-TEST&window=0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(stats_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({"error": "window must be an integer between 1 and 100000000"})
+    );
+
+    // Test window = 100000001 (should fail)
+    let stats_request = Request::builder()
+        .uri("/stats/?symbol=WIN-TEST&window=100000001")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(stats_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Test both exponent and window (should fail)
+    let stats_request = Request::builder()
+        .uri("/stats/?symbol=WIN-TEST&exponent=1&window=10")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(stats_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({"error": "Only one of exponent or window may be provided"})
+    );
+
+    // Test neither exponent nor window (should fail)
+    let stats_request = Request::builder()
+        .uri("/stats/?symbol=WIN-TEST")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(stats_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({"error": "Either exponent or window must be provided"})
+    );
+}
+
+/// Test that a non-power-of-ten window covers exactly the requested points
+#[tokio::test]
+async fn test_arbitrary_window_size() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+
+    // 1..=1440, so the last 390 points are 1051..=1440.
+    let request_body = json!({ "symbol": "WIN-390", "values": (1..=1440).map(|i| i as f64).collect::<Vec<f64>>() });
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let stats_request = Request::builder()
+        .uri("/stats/?symbol=WIN-390&window=390")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(stats_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stats: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["min"].as_f64().unwrap(), 1051.0);
+    assert_eq!(stats["max"].as_f64().unwrap(), 1440.0);
+    assert_eq!(stats["last"].as_f64().unwrap(), 1440.0);
+    assert!((stats["avg"].as_f64().unwrap() - 1245.5).abs() < 1e-9);
+    // Variance of 390 consecutive integers is (390^2 - 1) / 12.
+    assert!((stats["var"].as_f64().unwrap() - (390.0 * 390.0 - 1.0) / 12.0).abs() < 1e-6);
+}
+
 /// Test with very long symbol names
 #[tokio::test]
 async fn test_long_symbol_names() {
@@ -364,7 +581,7 @@ async fn test_malformed_json() {
     let state = SharedState::new(Store::new());
     let app = app_router(state);
 
-    let malformed_requests = vec![
+    let malformed_requests = [
         r#"{"symbol": "TEST", "values": [1,2,3"#, // Missing closing bracket
         r#"{"symbol": "TEST", "values": [1,2,]}"#, // Trailing comma
         r#"{"symbol": "TEST", "values": [1,2,3.}}"#, // Invalid number format
@@ -1,4 +1,10 @@
-use hft_service::{app_router, store::Store, SharedState};
+use hft_service::{
+    app_router, binary,
+    config::{LimitsConfig, RetentionConfig},
+    store::Store,
+    validation::InvalidValuePolicy,
+    SharedState,
+};
 
 use axum::body::{to_bytes, Body};
 use axum::http::{Request, StatusCode};
@@ -65,7 +71,7 @@ async fn test_reject_batch_with_negative_prices() {
     let body: Value = serde_json::from_slice(&body).unwrap();
     assert_eq!(
         body,
-        json!({"error": "Negative trading prices are not allowed"})
+        json!({"error": "Prices must be finite and non-negative; invalid values at indices [1]"})
     );
 }
 
@@ -119,6 +125,422 @@ async fn test_data_availability_errors() {
     assert_eq!(stats["last"].as_f64().unwrap(), 25.0);
     fuzzy_assert_eq(stats["avg"].as_f64().unwrap(), 15.0, "avg mismatch");
     fuzzy_assert_eq(stats["var"].as_f64().unwrap(), 50.0, "var mismatch");
+    // The deviations [-5, 5, -10, 0, 10] are symmetric, and flatter than normal.
+    assert!(stats["skewness"].as_f64().unwrap().abs() < 1e-9);
+    fuzzy_assert_eq(
+        stats["kurtosis"].as_f64().unwrap(),
+        -1.3,
+        "kurtosis mismatch",
+    );
+
+    // Only 5 of the 100 requested points contributed.
+    assert_eq!(stats["count"], 5);
+    fuzzy_assert_eq(stats["sum"].as_f64().unwrap(), 75.0, "sum mismatch");
+    assert_eq!(stats["start_index"], 0);
+    assert_eq!(stats["end_index"], 4);
+    assert_eq!(stats["truncated"], true);
+}
+
+#[tokio::test]
+async fn test_sample_variance_and_stddev() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+    state
+        .add_batch("DDOF", &[10.0, 20.0, 5.0, 15.0, 25.0])
+        .unwrap();
+
+    // Population variance by default, sample variance with ddof=1.
+    for (query, expected_var) in [("", 50.0), ("&ddof=0", 50.0), ("&ddof=1", 62.5)] {
+        let request = Request::builder()
+            .uri(format!("/stats/?symbol=DDOF&window=5{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let stats: Value = serde_json::from_slice(&body).unwrap();
+        fuzzy_assert_eq(stats["var"].as_f64().unwrap(), expected_var, "var mismatch");
+        fuzzy_assert_eq(
+            stats["stddev"].as_f64().unwrap(),
+            f64::sqrt(expected_var),
+            "stddev mismatch",
+        );
+        let expected_ddof = if query == "&ddof=1" { 1 } else { 0 };
+        assert_eq!(stats["ddof"], expected_ddof);
+        assert_eq!(stats["count"], 5);
+        assert_eq!(stats["truncated"], false);
+    }
+
+    // Only 0 and 1 are accepted, and a sample variance needs two points.
+    for query in ["window=5&ddof=2", "window=1&ddof=1"] {
+        let request = Request::builder()
+            .uri(format!("/stats/?symbol=DDOF&{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_multi_window_stats() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+    let values: Vec<f64> = (1..=500).map(|i| i as f64).collect();
+    state.add_batch("MULTI", &values).unwrap();
+
+    let request = Request::builder()
+        .uri("/stats/?symbol=MULTI&windows=10,100,1000&ddof=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let windows = body["windows"].as_array().unwrap();
+    assert_eq!(windows.len(), 3);
+
+    // Results come back in request order, each over the trailing points.
+    for (stats, (window, count)) in windows.iter().zip([(10, 10), (100, 100), (1000, 500)]) {
+        assert_eq!(stats["window"], window);
+        assert_eq!(stats["count"], count);
+        assert_eq!(stats["end_index"], 499);
+        assert_eq!(stats["truncated"], window > 500);
+        assert_eq!(stats["ddof"], 1);
+        assert_eq!(stats["last"].as_f64().unwrap(), 500.0);
+        fuzzy_assert_eq(
+            stats["avg"].as_f64().unwrap(),
+            500.0 - (count as f64 - 1.0) / 2.0,
+            "avg mismatch",
+        );
+    }
+
+    // Malformed lists, out-of-range windows and mixed forms are rejected.
+    let too_many = format!("windows={}", vec!["10"; 33].join(","));
+    for query in [
+        "windows=10,abc",
+        "windows=0,10",
+        "windows=",
+        "windows=10&exponent=2",
+        "windows=10&window=5",
+        "windows=10&duration=5m",
+        too_many.as_str(),
+    ] {
+        let request = Request::builder()
+            .uri(format!("/stats/?symbol=MULTI&{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+
+    // An oversized list is rejected for its length before any entry is parsed.
+    let request = Request::builder()
+        .uri(format!("/stats/?symbol=MULTI&{},abc", too_many))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "At most 32 windows may be requested at once");
+}
+
+/// Sends a request with the given `Accept` header, returning the status, the
+/// response's content type and its body decoded as whatever format it claims.
+async fn send_with_accept(
+    app: &axum::Router,
+    request: axum::http::request::Builder,
+    body: Body,
+    accept: &str,
+) -> (StatusCode, String, Value) {
+    let request = request.header("accept", accept).body(body).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let content_type = response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = match content_type.as_str() {
+        "application/msgpack" => rmp_serde::from_slice(&bytes).unwrap(),
+        "application/cbor" => ciborium::from_reader(bytes.as_ref()).unwrap(),
+        _ => serde_json::from_slice(&bytes).unwrap(),
+    };
+    (status, content_type, body)
+}
+
+/// Asserts that two decoded bodies have the same structure and values. Floats
+/// only need to match closely, since serde_json doesn't parse every float
+/// back to exactly the value that was encoded.
+fn assert_same_body(actual: &Value, expected: &Value, message: &str) {
+    match (actual, expected) {
+        (Value::Object(a), Value::Object(b)) => {
+            assert_eq!(
+                a.keys().collect::<Vec<_>>(),
+                b.keys().collect::<Vec<_>>(),
+                "{}",
+                message
+            );
+            for (key, value) in a {
+                assert_same_body(value, &b[key], message);
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            assert_eq!(a.len(), b.len(), "{}", message);
+            for (a, b) in a.iter().zip(b) {
+                assert_same_body(a, b, message);
+            }
+        }
+        (Value::Number(a), Value::Number(b)) if a.is_f64() || b.is_f64() => {
+            let (a, b) = (a.as_f64().unwrap(), b.as_f64().unwrap());
+            assert!((a - b).abs() <= 1e-12 * a.abs().max(b.abs()), "{}", message);
+        }
+        _ => assert_eq!(actual, expected, "{}", message),
+    }
+}
+
+#[tokio::test]
+async fn test_response_format_negotiation() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+    state.add_batch("AAA", &[1.0, 2.5, 3.0, 4.0]).unwrap();
+
+    let get = |uri: &str| Request::builder().uri(uri);
+    let batch = || {
+        Request::builder()
+            .uri("/stats/batch")
+            .method("POST")
+            .header("content-type", "application/json")
+    };
+    let batch_body = || {
+        Body::from(
+            json!({ "queries": [{ "symbol": "AAA", "window": 2 }, { "symbol": "NOPE", "window": 2 }] })
+                .to_string(),
+        )
+    };
+
+    for (accept, content_type) in [
+        ("application/msgpack", "application/msgpack"),
+        ("application/cbor", "application/cbor"),
+        (
+            "application/json;q=0.5, application/x-msgpack",
+            "application/msgpack",
+        ),
+    ] {
+        // Successful responses carry the same fields and values as JSON.
+        for uri in [
+            "/stats/?symbol=AAA&window=3",
+            "/stats/?symbol=AAA&windows=2,10",
+            "/quantiles/?symbol=AAA&window=4&q=0.5",
+            "/symbols/AAA",
+        ] {
+            let (_, _, expected) = send_with_accept(&app, get(uri), Body::empty(), "*/*").await;
+            let (status, actual_type, body) =
+                send_with_accept(&app, get(uri), Body::empty(), accept).await;
+            assert_eq!(status, StatusCode::OK, "{} {}", accept, uri);
+            assert_eq!(actual_type, content_type, "{}", uri);
+            assert_same_body(&body, &expected, &format!("{} {}", accept, uri));
+        }
+        let (_, _, expected) =
+            send_with_accept(&app, batch(), batch_body(), "application/json").await;
+        let (_, _, body) = send_with_accept(&app, batch(), batch_body(), accept).await;
+        assert_same_body(&body, &expected, accept);
+
+        // So do errors.
+        let (status, actual_type, body) = send_with_accept(
+            &app,
+            get("/stats/?symbol=NOPE&window=3"),
+            Body::empty(),
+            accept,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(actual_type, content_type);
+        assert_eq!(body, json!({ "error": "NOPE" }));
+    }
+
+    // Unsupported types fall back to JSON.
+    let (status, content_type, body) =
+        send_with_accept(&app, get("/health"), Body::empty(), "text/html").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/json");
+    assert_eq!(body, json!({ "status": "ok" }));
+}
+
+#[tokio::test]
+async fn test_batch_stats() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+    state.add_batch("AAA", &[1.0, 2.0, 3.0, 4.0]).unwrap();
+    state.add_batch("BBB", &[10.0, 20.0]).unwrap();
+
+    let request = Request::builder()
+        .uri("/stats/batch")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({
+                "queries": [
+                    { "symbol": "AAA", "window": 2 },
+                    { "symbol": "BBB", "exponent": 1, "ddof": 1 },
+                    { "symbol": "NOPE", "window": 10 },
+                    { "symbol": "AAA", "exponent": 99 },
+                    { "symbol": "BBB", "window": 1, "ddof": 1 }
+                ]
+            })
+            .to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 5);
+
+    // Results come back in request order, each with its own outcome.
+    assert_eq!(results[0]["symbol"], "AAA");
+    assert_eq!(results[0]["count"], 2);
+    fuzzy_assert_eq(results[0]["avg"].as_f64().unwrap(), 3.5, "avg mismatch");
+    assert!(results[0].get("error").is_none());
+
+    assert_eq!(results[1]["symbol"], "BBB");
+    assert_eq!(results[1]["truncated"], true);
+    fuzzy_assert_eq(results[1]["var"].as_f64().unwrap(), 50.0, "var mismatch");
+
+    assert_eq!(results[2]["symbol"], "NOPE");
+    assert_eq!(results[2]["status"], 404);
+    assert!(results[2]["error"].as_str().unwrap().contains("NOPE"));
+    assert!(results[2].get("count").is_none());
+
+    assert_eq!(results[3]["status"], 400);
+    assert_eq!(results[4]["status"], 400);
+
+    // An oversized batch is rejected as a whole.
+    let queries: Vec<Value> = (0..1001)
+        .map(|_| json!({ "symbol": "AAA", "window": 1 }))
+        .collect();
+    let request = Request::builder()
+        .uri("/stats/batch")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "queries": queries }).to_string()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Posts a multi-symbol ingestion request and returns its status and body.
+async fn post_batches(app: &axum::Router, body: Value) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .uri("/add_batches/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_multi_symbol_ingestion_atomic() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+
+    // Interleaved batches for two symbols are applied in order.
+    let (status, body) = post_batches(
+        &app,
+        json!({
+            "batches": [
+                { "symbol": "AAA", "values": [1.0, 2.0], "timestamps": [1000, 2000] },
+                { "symbol": "BBB", "values": [10.0] },
+                { "symbol": "AAA", "values": [3.0], "timestamps": [3000] }
+            ]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "success");
+    assert_eq!(body["applied"], 3);
+    assert_eq!(body["results"][1]["symbol"], "BBB");
+    assert_eq!(body["results"][1]["status"], 200);
+    assert_eq!(state.symbols.get("AAA").unwrap().len(), 3);
+    assert_eq!(state.symbols.get("BBB").unwrap().len(), 1);
+
+    // One invalid batch rejects the whole request, naming the culprit.
+    let (status, body) = post_batches(
+        &app,
+        json!({
+            "mode": "atomic",
+            "batches": [
+                { "symbol": "AAA", "values": [4.0] },
+                { "symbol": "CCC", "values": [-1.0] }
+            ]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"].as_str().unwrap().contains("batches[1] (CCC)"));
+    assert_eq!(state.symbols.get("AAA").unwrap().len(), 3);
+    assert!(!state.symbols.contains_key("CCC"));
+
+    // Timestamps must also continue across batches of the same request.
+    let (status, _) = post_batches(
+        &app,
+        json!({
+            "batches": [
+                { "symbol": "AAA", "values": [4.0], "timestamps": [5000] },
+                { "symbol": "AAA", "values": [5.0], "timestamps": [4000] }
+            ]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(state.symbols.get("AAA").unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_multi_symbol_ingestion_best_effort() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+
+    // The default limit allows 10 symbols, so the eleventh and twelfth are
+    // refused along with the invalid batches.
+    let mut batches: Vec<Value> = (0..12)
+        .map(|i| json!({ "symbol": format!("SYM-{}", i), "values": [1.0, 2.0] }))
+        .collect();
+    batches.insert(3, json!({ "symbol": "SYM-0", "values": [] }));
+    batches.insert(5, json!({ "symbol": "SYM-1", "values": [-2.0] }));
+
+    let (status, body) =
+        post_batches(&app, json!({ "mode": "best_effort", "batches": batches })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "partial");
+    assert_eq!(body["applied"], 10);
+
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 14);
+    let failed: Vec<usize> = results
+        .iter()
+        .enumerate()
+        .filter(|(_, result)| result["status"] != 200)
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(failed, vec![3, 5, 12, 13]);
+    assert!(results[12]["error"]
+        .as_str()
+        .unwrap()
+        .contains("Maximum number of unique symbols"));
+    assert!(results[0].get("error").is_none());
+
+    assert_eq!(state.symbols.len(), 10);
+    assert_eq!(state.symbols.get("SYM-1").unwrap().len(), 2);
+
+    // Unknown modes are rejected.
+    let (status, _) = post_batches(&app, json!({ "mode": "sometimes", "batches": [] })).await;
+    assert!(status.is_client_error());
 }
 
 #[tokio::test]
@@ -246,6 +668,633 @@ async fn test_rejects_eleventh_symbol() {
     assert_eq!(response_existing.status(), StatusCode::OK);
 }
 
+#[tokio::test]
+async fn test_configured_limits() {
+    let limits = LimitsConfig {
+        max_batch_size: 5,
+        max_symbols: 200,
+        max_exponent: 2,
+        ..LimitsConfig::default()
+    };
+    let app = app_router(SharedState::new(Store::new().with_limits(limits)));
+
+    // Far more symbols than the default cap of 10 are accepted.
+    for i in 0..200 {
+        let request = Request::builder()
+            .uri("/add_batch/")
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({ "symbol": format!("SYM-{}", i), "values": [100.0] }).to_string(),
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "symbol {} rejected", i);
+    }
+
+    // The 201st symbol hits the configured cap.
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({ "symbol": "SYM-200", "values": [100.0] }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body["error"],
+        "Maximum number of unique symbols (200) reached."
+    );
+
+    // Batches are capped at the configured size.
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({ "symbol": "SYM-0", "values": vec![1.0; 6] }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "Batch size cannot exceed 5 values.");
+
+    // The exponent range follows the configured bounds.
+    let request = Request::builder()
+        .uri("/stats/?symbol=SYM-0&exponent=3")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "exponent must be an integer between 1 and 2");
+}
+
+async fn post_binary_batch(app: &axum::Router, body: Vec<u8>) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", binary::BATCH_CONTENT_TYPE)
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_binary_batches() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+
+    let batch = binary::encode_batch("ABC", &[1.0, 2.0], Some(&[10, 20]), Default::default());
+    let (status, body) = post_binary_batch(&app, batch).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({"status": "success"}));
+
+    // Binary batches are validated exactly like JSON ones.
+    let batch = binary::encode_batch("ABC", &[3.0, -1.0], None, Default::default());
+    let (status, body) = post_binary_batch(&app, batch).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body,
+        json!({"error": "Prices must be finite and non-negative; invalid values at indices [1]"})
+    );
+    let batch = binary::encode_batch("ABC", &[3.0], Some(&[5]), Default::default());
+    let (status, _) = post_binary_batch(&app, batch).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let batch = binary::encode_batch(
+        "ABC",
+        &[f64::NAN, 3.0],
+        Some(&[30, 40]),
+        InvalidValuePolicy::Drop,
+    );
+    let (status, body) = post_binary_batch(&app, batch).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({"status": "success", "dropped": [0]}));
+
+    // Malformed bodies are rejected with the error format of every other endpoint.
+    let (status, body) = post_binary_batch(&app, b"ABC".to_vec()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("Invalid binary batch"));
+
+    let stats = state.get_stats("ABC", 10).unwrap();
+    assert_eq!((stats.count, stats.sum), (3, 6.0));
+}
+
+#[tokio::test]
+async fn test_capacity_hint() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+
+    // A hint sizes the new symbol's storage up front.
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({ "symbol": "HINTED", "values": [1.0, 2.0], "capacity_hint": 5000 }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(state.symbols.get("HINTED").unwrap().capacity(), 5000);
+
+    // Hints above the configured maximum are rejected before anything is stored.
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({ "symbol": "GREEDY", "values": [1.0], "capacity_hint": 100_001 }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "capacity_hint cannot exceed 100000");
+    assert!(state.symbols.get("GREEDY").is_none());
+}
+
+#[tokio::test]
+async fn test_symbol_listing_and_metadata() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+
+    for (symbol, values) in [
+        ("BTC-USD", vec![100.0, 101.0, 102.0]),
+        ("ETH-USD", vec![10.0]),
+        ("BTC-EUR", vec![90.0, 91.0]),
+    ] {
+        let request = Request::builder()
+            .uri("/add_batch/")
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({ "symbol": symbol, "values": values }).to_string(),
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Symbols are listed in name order, filtered by prefix and paged.
+    let request = Request::builder()
+        .uri("/symbols?prefix=BTC-&limit=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["symbols"].as_array().unwrap().len(), 1);
+    assert_eq!(body["symbols"][0]["symbol"], "BTC-EUR");
+
+    let request = Request::builder()
+        .uri("/symbols?prefix=BTC-&offset=1&limit=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["symbols"][0]["symbol"], "BTC-USD");
+
+    let request = Request::builder()
+        .uri("/symbols")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["total"], 3);
+
+    // A single symbol's metadata.
+    let request = Request::builder()
+        .uri("/symbols/BTC-USD")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["points"], 3);
+    assert_eq!(body["first_value"], 100.0);
+    assert_eq!(body["last_value"], 102.0);
+    assert!(body["capacity"].as_u64().unwrap() >= 3);
+    assert!(body["memory_bytes"].as_u64().unwrap() > 0);
+    assert!(body["created_at"].as_u64().unwrap() <= body["updated_at"].as_u64().unwrap());
+
+    let request = Request::builder()
+        .uri("/symbols/NOPE")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = Request::builder()
+        .uri("/symbols?limit=0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_quantiles() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+
+    // 1..=100, shuffled.
+    let values: Vec<f64> = (0..100).map(|i| ((i * 37) % 100 + 1) as f64).collect();
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({ "symbol": "QTL", "values": values }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder()
+        .uri("/quantiles/?symbol=QTL&window=100&q=0.5,0.95,0.99")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["count"], 100);
+    let quantiles = body["quantiles"].as_array().unwrap();
+    assert_eq!(quantiles.len(), 3);
+    assert_eq!(quantiles[0]["q"], 0.5);
+    fuzzy_assert_eq(quantiles[0]["value"].as_f64().unwrap(), 50.5, "median");
+    fuzzy_assert_eq(quantiles[1]["value"].as_f64().unwrap(), 95.05, "p95");
+    fuzzy_assert_eq(quantiles[2]["value"].as_f64().unwrap(), 99.01, "p99");
+
+    // Quantiles outside [0, 1] and malformed lists are rejected.
+    for q in ["1.5", "-0.1", "0.5,abc", ""] {
+        let request = Request::builder()
+            .uri(format!("/quantiles/?symbol=QTL&window=10&q={}", q))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "q={}", q);
+    }
+
+    let request = Request::builder()
+        .uri("/quantiles/?symbol=NOPE&window=10&q=0.5")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_approximate_quantiles() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state.clone());
+
+    // 0..100_000, shuffled, so the window is mostly covered by sketches.
+    let values: Vec<f64> = (0..100_000u64)
+        .map(|i| ((i * 7_919) % 100_000) as f64)
+        .collect();
+    for chunk in values.chunks(10_000) {
+        state.add_batch("APPROX", chunk).unwrap();
+    }
+
+    let request = Request::builder()
+        .uri("/quantiles/?symbol=APPROX&window=100000&q=0.5,0.99&accuracy=0.01")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["count"], 100_000);
+    let rank_error = body["rank_error"].as_f64().unwrap();
+    assert!(rank_error > 0.0 && rank_error <= 0.01, "{}", rank_error);
+
+    // Every value is within the reported rank error of the exact quantile,
+    // which for 0..100_000 is the value itself.
+    let quantiles = body["quantiles"].as_array().unwrap();
+    for (quantile, exact) in quantiles.iter().zip([49_999.0, 98_999.0]) {
+        let value = quantile["value"].as_f64().unwrap();
+        assert!(
+            (value - exact).abs() <= rank_error * 100_000.0,
+            "{} vs {}",
+            value,
+            exact
+        );
+    }
+
+    // Exact queries report no error bound.
+    let request = Request::builder()
+        .uri("/quantiles/?symbol=APPROX&window=100&q=0.5")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert!(body.get("rank_error").is_none());
+
+    // Accuracy finer than the sketches, or above 1, is rejected.
+    for accuracy in ["0.001", "1.5", "-1"] {
+        let request = Request::builder()
+            .uri(format!(
+                "/quantiles/?symbol=APPROX&window=10&q=0.5&accuracy={}",
+                accuracy
+            ))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "accuracy={}",
+            accuracy
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_range_stats() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+    let symbol = "RANGE-SYM";
+
+    // Scenario 1: Symbol does not exist
+    let request = Request::builder()
+        .uri(format!("/range_stats/?symbol={}&start=0&end=1", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let add_request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(&json!({
+                "symbol": symbol,
+                "values": [10.0, 20.0, 5.0, 15.0, 25.0]
+            }))
+            .unwrap(),
+        ))
+        .unwrap();
+    let add_response = app.clone().oneshot(add_request).await.unwrap();
+    assert_eq!(add_response.status(), StatusCode::OK);
+
+    // Scenario 2: A range in the past, excluding the latest point.
+    let request = Request::builder()
+        .uri(format!("/range_stats/?symbol={}&start=0&end=3", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stats: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["count"].as_u64().unwrap(), 4);
+    assert_eq!(stats["min"].as_f64().unwrap(), 5.0);
+    assert_eq!(stats["max"].as_f64().unwrap(), 20.0);
+    fuzzy_assert_eq(stats["avg"].as_f64().unwrap(), 12.5, "avg mismatch");
+    fuzzy_assert_eq(stats["var"].as_f64().unwrap(), 31.25, "var mismatch");
+
+    // Scenario 3: The range extends past the last index.
+    let request = Request::builder()
+        .uri(format!("/range_stats/?symbol={}&start=2&end=5", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({"error": "Index range [2, 5] is out of bounds for 5 data points"})
+    );
+
+    // Scenario 4: An inverted range.
+    let request = Request::builder()
+        .uri(format!("/range_stats/?symbol={}&start=3&end=1", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_time_window_stats() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+    let symbol = "TIME-SYM";
+
+    // Ten points, one minute apart, starting at t = 0.
+    let add_request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(&json!({
+                "symbol": symbol,
+                "values": (1..=10).map(|i| i as f64).collect::<Vec<f64>>(),
+                "start_timestamp": 0,
+                "interval_ms": 60_000
+            }))
+            .unwrap(),
+        ))
+        .unwrap();
+    let add_response = app.clone().oneshot(add_request).await.unwrap();
+    assert_eq!(add_response.status(), StatusCode::OK);
+
+    // The last five minutes ending at the final point: t in [240000, 540000].
+    let request = Request::builder()
+        .uri(format!(
+            "/stats/?symbol={}&duration=5m&until=540000",
+            symbol
+        ))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stats: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["min"].as_f64().unwrap(), 5.0);
+    assert_eq!(stats["max"].as_f64().unwrap(), 10.0);
+    assert_eq!(stats["last"].as_f64().unwrap(), 10.0);
+    fuzzy_assert_eq(stats["avg"].as_f64().unwrap(), 7.5, "avg mismatch");
+
+    // An explicit [since, until] range in the past.
+    let request = Request::builder()
+        .uri(format!(
+            "/stats/?symbol={}&since=60000&until=180000",
+            symbol
+        ))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stats: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["min"].as_f64().unwrap(), 2.0);
+    assert_eq!(stats["max"].as_f64().unwrap(), 4.0);
+    assert_eq!(stats["last"].as_f64().unwrap(), 4.0);
+
+    // A time range containing no points.
+    let request = Request::builder()
+        .uri(format!("/stats/?symbol={}&since=600000", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // An unparseable duration.
+    let request = Request::builder()
+        .uri(format!("/stats/?symbol={}&duration=5y", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("Invalid duration '5y'"));
+
+    // Count-based and time-based windows cannot be mixed.
+    let request = Request::builder()
+        .uri(format!("/stats/?symbol={}&window=5&duration=5m", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_reject_out_of_order_timestamps() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+
+    let request_body = json!({
+        "symbol": "TS-ORDER",
+        "values": [1.0, 2.0, 3.0],
+        "timestamps": [3000, 1000, 2000]
+    });
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Mismatched lengths are rejected as well.
+    let request_body = json!({
+        "symbol": "TS-ORDER",
+        "values": [1.0, 2.0, 3.0],
+        "timestamps": [1000, 2000]
+    });
+    let request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(&request_body).unwrap()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({"error": "timestamps must have the same length as values"})
+    );
+}
+
+#[tokio::test]
+async fn test_stats_with_bounded_retention() {
+    let state = SharedState::new(Store::new().with_retention(RetentionConfig {
+        max_points: 1_000,
+        ..Default::default()
+    }));
+    let app = app_router(state);
+    let symbol = "RETAINED";
+
+    // Add 2,500 points so the 1,000-point buffer wraps around more than twice.
+    for batch in 0..5 {
+        let values: Vec<f64> = (batch * 500 + 1..=(batch + 1) * 500)
+            .map(|v| v as f64)
+            .collect();
+        let add_request = Request::builder()
+            .uri("/add_batch/")
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                serde_json::to_string(&json!({ "symbol": symbol, "values": values })).unwrap(),
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(add_request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // The largest window is still answerable and covers the retained points 1501..=2500.
+    let stats_request = Request::builder()
+        .uri(format!("/stats/?symbol={}&exponent=8", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(stats_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let stats: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["min"].as_f64().unwrap(), 1501.0);
+    assert_eq!(stats["max"].as_f64().unwrap(), 2500.0);
+    assert_eq!(stats["last"].as_f64().unwrap(), 2500.0);
+    fuzzy_assert_eq(stats["avg"].as_f64().unwrap(), 2000.5, "avg mismatch");
+    fuzzy_assert_eq(
+        stats["var"].as_f64().unwrap(),
+        (1000.0 * 1000.0 - 1.0) / 12.0,
+        "var mismatch",
+    );
+
+    // Indices that have been overwritten can no longer be queried.
+    let request = Request::builder()
+        .uri(format!("/range_stats/?symbol={}&start=0&end=10", symbol))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({"error": "Index 0 is no longer retained; the oldest retained index is 1500"})
+    );
+}
+
 // This test is ignored by default because it is resource-intensive.
 // To run it, use: cargo test --release -- --ignored
 #[tokio::test]
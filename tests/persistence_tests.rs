@@ -0,0 +1,556 @@
+use hft_service::{
+    app_router,
+    config::RetentionConfig,
+    snapshot,
+    store::{Store, SymbolBatch},
+    validation::InvalidValuePolicy,
+    wal::{FsyncPolicy, Wal},
+    SharedState,
+};
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tower::ServiceExt;
+
+/// A helper for comparing floating-point numbers in tests.
+fn fuzzy_assert_eq(a: f64, b: f64) {
+    let epsilon = 1e-9;
+    assert!((a - b).abs() < epsilon, "Expected {}, got {}", b, a);
+}
+
+/// Builds a store where every symbol keeps at most `limit` points.
+fn retention(limit: usize) -> RetentionConfig {
+    RetentionConfig {
+        max_points: limit,
+        ..Default::default()
+    }
+}
+
+/// Test that every symbol's values, timestamps and history length survive a restart
+#[test]
+fn test_snapshot_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store.snapshot");
+
+    let store = Store::new().with_snapshot_path(&path);
+    let values: Vec<f64> = (1..=1000).map(|i| i as f64).collect();
+    let timestamps: Vec<u64> = (0..1000).map(|i| i * 1_000).collect();
+    store
+        .add_batch_with_timestamps("AAA", &values, &timestamps)
+        .unwrap();
+    store.add_batch("BBB", &[5.0, 10.0, 15.0]).unwrap();
+
+    let summary = store.save_snapshot().unwrap();
+    assert_eq!(summary.symbols, 2);
+    assert_eq!(summary.points, 1003);
+
+    // Restore into a fresh store.
+    let restored = Store::new().with_snapshot_path(&path);
+    let summary = restored.load_snapshot().unwrap().unwrap();
+    assert_eq!(summary.symbols, 2);
+
+    for window in [1, 10, 390, 1000] {
+        let expected = store.get_stats("AAA", window).unwrap();
+        let actual = restored.get_stats("AAA", window).unwrap();
+        assert_eq!(actual.min, expected.min);
+        assert_eq!(actual.max, expected.max);
+        assert_eq!(actual.last, expected.last);
+        fuzzy_assert_eq(actual.avg, expected.avg);
+        fuzzy_assert_eq(actual.var, expected.var);
+    }
+
+    // Timestamps were restored, so time windows still work.
+    let stats = restored.get_stats_between("AAA", 990_000, 999_000).unwrap();
+    assert_eq!(stats.min, 991.0);
+    assert_eq!(stats.max, 1000.0);
+
+    // Creation and update times are part of the snapshot.
+    let (original, loaded) = (
+        store.symbol_info("AAA").unwrap(),
+        restored.symbol_info("AAA").unwrap(),
+    );
+    assert_eq!(loaded.created_at, original.created_at);
+    assert_eq!(loaded.updated_at, original.updated_at);
+
+    // New data keeps appending after the restored history.
+    restored.add_batch("BBB", &[20.0]).unwrap();
+    let stats = restored.get_range_stats("BBB", 0, 3).unwrap();
+    assert_eq!(stats.count, 4);
+    fuzzy_assert_eq(stats.avg, 12.5);
+}
+
+/// Test that a wrapped ring buffer is restored with its absolute indices intact
+#[test]
+fn test_snapshot_round_trip_with_retention() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store.snapshot");
+
+    let store = Store::new()
+        .with_retention(retention(100))
+        .with_snapshot_path(&path);
+    let values: Vec<f64> = (0..250).map(|i| i as f64).collect();
+    store.add_batch("RING", &values).unwrap();
+    store.save_snapshot().unwrap();
+
+    let restored = Store::new()
+        .with_retention(retention(100))
+        .with_snapshot_path(&path);
+    restored.load_snapshot().unwrap();
+
+    // Only absolute indices 150..=249 are retained.
+    let stats = restored.get_range_stats("RING", 150, 249).unwrap();
+    assert_eq!(stats.count, 100);
+    assert_eq!(stats.min, 150.0);
+    assert!(restored.get_range_stats("RING", 149, 249).is_err());
+
+    // Appending after the restore keeps wrapping correctly.
+    let more: Vec<f64> = (250..280).map(|i| i as f64).collect();
+    restored.add_batch("RING", &more).unwrap();
+    let stats = restored.get_stats("RING", 1_000).unwrap();
+    assert_eq!(stats.min, 180.0);
+    assert_eq!(stats.max, 279.0);
+    assert_eq!(stats.last, 279.0);
+    fuzzy_assert_eq(stats.avg, 229.5);
+
+    // A smaller retention limit at restore time keeps only the newest points.
+    let shrunk = Store::new()
+        .with_retention(retention(10))
+        .with_snapshot_path(&path);
+    shrunk.load_snapshot().unwrap();
+    let stats = shrunk.get_stats("RING", 1_000).unwrap();
+    assert_eq!(stats.min, 240.0);
+    assert_eq!(stats.max, 249.0);
+}
+
+/// Test that a damaged snapshot is rejected without touching the store
+#[test]
+fn test_corrupt_snapshot_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store.snapshot");
+
+    let store = Store::new().with_snapshot_path(&path);
+    store.add_batch("AAA", &[1.0, 2.0, 3.0]).unwrap();
+    store.save_snapshot().unwrap();
+
+    // Flip a bit inside the stored values.
+    let mut bytes = fs::read(&path).unwrap();
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 0x01;
+    fs::write(&path, &bytes).unwrap();
+
+    let restored = Store::new().with_snapshot_path(&path);
+    assert!(restored.load_snapshot().is_err());
+    assert!(restored.symbols.is_empty());
+
+    // A truncated file is rejected too.
+    fs::write(&path, &bytes[..bytes.len() - 6]).unwrap();
+    assert!(restored.load_snapshot().is_err());
+    assert!(restored.symbols.is_empty());
+}
+
+/// Test that a corrupt symbol name length fails the load instead of being allocated
+#[test]
+fn test_snapshot_with_oversized_name_length_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store.snapshot");
+
+    let mut bytes = b"HFTSNAP\0".to_vec();
+    bytes.extend_from_slice(&snapshot::SNAPSHOT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&1u64.to_le_bytes());
+    bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+    bytes.extend_from_slice(b"AAA");
+    fs::write(&path, &bytes).unwrap();
+
+    let store = Store::new().with_snapshot_path(&path);
+    assert!(store.load_snapshot().is_err());
+    assert!(store.symbols.is_empty());
+}
+
+/// Test that a missing snapshot file is not an error on first start
+#[test]
+fn test_missing_snapshot_is_not_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::new().with_snapshot_path(dir.path().join("none.snapshot"));
+    assert!(store.load_snapshot().unwrap().is_none());
+
+    // Without a snapshot path there is nothing to load.
+    assert!(Store::new().load_snapshot().unwrap().is_none());
+}
+
+/// Test the admin endpoint that triggers a snapshot on demand
+#[tokio::test]
+async fn test_admin_snapshot_endpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nested").join("store.snapshot");
+
+    let state = SharedState::new(Store::new().with_snapshot_path(&path));
+    let app = app_router(state);
+
+    let add_request = Request::builder()
+        .uri("/add_batch/")
+        .method("POST")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(&json!({ "symbol": "SNAP", "values": [1.0, 2.0] })).unwrap(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(add_request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder()
+        .uri("/admin/snapshot")
+        .method("POST")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({"status": "success", "symbols": 1, "points": 2})
+    );
+    assert!(path.exists());
+
+    // Without a configured path the endpoint reports that snapshots are disabled.
+    let app = app_router(SharedState::new(Store::new()));
+    let request = Request::builder()
+        .uri("/admin/snapshot")
+        .method("POST")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Opens a store backed by a write-ahead log in `dir`, as a restarted process would.
+fn open_store_with_wal(dir: &Path) -> Store {
+    Store::new().with_wal(Wal::open(dir, FsyncPolicy::EveryBatch).unwrap())
+}
+
+/// Returns the write-ahead log segments in `dir`, oldest first.
+fn wal_segments(dir: &Path) -> Vec<PathBuf> {
+    let mut segments: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wal"))
+        .collect();
+    segments.sort();
+    segments
+}
+
+/// Returns the only non-empty segment in `dir`, i.e. the one holding records.
+fn segment_with_records(dir: &Path) -> PathBuf {
+    let segments: Vec<PathBuf> = wal_segments(dir)
+        .into_iter()
+        .filter(|path| fs::metadata(path).unwrap().len() > 12)
+        .collect();
+    assert_eq!(
+        segments.len(),
+        1,
+        "Expected exactly one segment with records"
+    );
+    segments.into_iter().next().unwrap()
+}
+
+/// Test that batches survive a crash with no snapshot at all
+#[test]
+fn test_wal_replay_after_crash() {
+    let dir = tempfile::tempdir().unwrap();
+
+    {
+        let store = open_store_with_wal(dir.path());
+        store.add_batch("AAA", &[1.0, 2.0, 3.0]).unwrap();
+        store
+            .add_batch_with_timestamps("BBB", &[10.0, 20.0], &[1_000, 2_000])
+            .unwrap();
+        store.add_batch("AAA", &[4.0]).unwrap();
+        // The store is dropped without a snapshot, as in a crash.
+    }
+
+    let restored = open_store_with_wal(dir.path());
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 3);
+    assert_eq!(summary.truncated_bytes, 0);
+
+    let stats = restored.get_stats("AAA", 100).unwrap();
+    assert_eq!(stats.min, 1.0);
+    assert_eq!(stats.last, 4.0);
+    fuzzy_assert_eq(stats.avg, 2.5);
+    let stats = restored.get_stats_between("BBB", 0, 1_500).unwrap();
+    assert_eq!(stats.last, 10.0);
+}
+
+/// Test that concurrent writers, whose syncs are shared, all replay in order
+#[test]
+fn test_wal_replay_after_concurrent_writes() {
+    let dir = tempfile::tempdir().unwrap();
+
+    {
+        let store = open_store_with_wal(dir.path());
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let store = &store;
+                scope.spawn(move || {
+                    let symbol = format!("SYM{}", thread % 4);
+                    for i in 0..50 {
+                        store.add_batch(&symbol, &[i as f64, i as f64]).unwrap();
+                    }
+                });
+            }
+        });
+    }
+
+    let restored = open_store_with_wal(dir.path());
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 400);
+    for symbol in ["SYM0", "SYM1", "SYM2", "SYM3"] {
+        let stats = restored.get_stats(symbol, 1_000).unwrap();
+        assert_eq!(stats.count, 200);
+        fuzzy_assert_eq(stats.sum, 2.0 * 2.0 * (0..50).sum::<i32>() as f64);
+    }
+}
+
+/// Test that a half-written final record is detected and truncated
+#[test]
+fn test_wal_torn_tail_is_truncated() {
+    let dir = tempfile::tempdir().unwrap();
+
+    {
+        let store = open_store_with_wal(dir.path());
+        store.add_batch("AAA", &[1.0, 2.0, 3.0]).unwrap();
+        store.add_batch("AAA", &[4.0, 5.0, 6.0]).unwrap();
+    }
+
+    // Simulate a crash in the middle of writing the second record.
+    let segment = segment_with_records(dir.path());
+    let full_len = fs::metadata(&segment).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&segment).unwrap();
+    file.set_len(full_len - 5).unwrap();
+    drop(file);
+
+    let restored = open_store_with_wal(dir.path());
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 1);
+    assert!(summary.truncated_bytes > 0);
+
+    // Only the intact first batch is applied.
+    let stats = restored.get_stats("AAA", 100).unwrap();
+    assert_eq!(stats.max, 3.0);
+    assert_eq!(stats.last, 3.0);
+    assert_eq!(restored.symbols.get("AAA").unwrap().len(), 3);
+
+    // The torn bytes are gone, so the log is consistent for the next restart.
+    assert!(fs::metadata(&segment).unwrap().len() < full_len - 5);
+    restored.add_batch("AAA", &[7.0]).unwrap();
+    drop(restored);
+
+    let restored = open_store_with_wal(dir.path());
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 2);
+    assert_eq!(summary.truncated_bytes, 0);
+    let stats = restored.get_stats("AAA", 100).unwrap();
+    assert_eq!(stats.last, 7.0);
+    fuzzy_assert_eq(stats.avg, 13.0 / 4.0);
+}
+
+/// Writes `AAA` and `BBB` to `store` as one atomic ingestion.
+fn add_atomic_pair(store: &Store) {
+    fn batch<'a>(symbol: &'a str, values: &'a [f64]) -> SymbolBatch<'a> {
+        SymbolBatch {
+            symbol,
+            values,
+            timestamps: None,
+            policy: InvalidValuePolicy::Reject,
+            capacity_hint: None,
+        }
+    }
+    store
+        .add_batches_atomic(&[batch("AAA", &[1.0, 2.0]), batch("BBB", &[3.0, 4.0])])
+        .unwrap();
+}
+
+/// Test that an atomic ingestion replays as a single record
+#[test]
+fn test_wal_replays_atomic_ingestion_as_one_record() {
+    let dir = tempfile::tempdir().unwrap();
+
+    {
+        let store = open_store_with_wal(dir.path());
+        add_atomic_pair(&store);
+    }
+
+    let restored = open_store_with_wal(dir.path());
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 1);
+    assert_eq!(restored.get_stats("AAA", 100).unwrap().last, 2.0);
+    assert_eq!(restored.get_stats("BBB", 100).unwrap().last, 4.0);
+}
+
+/// Test that a torn atomic ingestion replays none of its batches
+#[test]
+fn test_wal_torn_atomic_ingestion_is_dropped_whole() {
+    let dir = tempfile::tempdir().unwrap();
+
+    {
+        let store = open_store_with_wal(dir.path());
+        add_atomic_pair(&store);
+    }
+
+    // Simulate a crash while writing the last batch of the group.
+    let segment = segment_with_records(dir.path());
+    let full_len = fs::metadata(&segment).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&segment).unwrap();
+    file.set_len(full_len - 5).unwrap();
+    drop(file);
+
+    let restored = open_store_with_wal(dir.path());
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 0);
+    assert!(summary.truncated_bytes > 0);
+    assert!(restored.symbols.get("AAA").is_none());
+    assert!(restored.symbols.get("BBB").is_none());
+}
+
+/// Test that trailing garbage and checksum failures end the replay cleanly
+#[test]
+fn test_wal_corrupt_records_are_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+
+    {
+        let store = open_store_with_wal(dir.path());
+        store.add_batch("AAA", &[1.0, 2.0]).unwrap();
+        store.add_batch("AAA", &[3.0, 4.0]).unwrap();
+    }
+
+    // Flip a bit in the last value of the second record so its checksum fails,
+    // then append a frame header that promises more bytes than exist.
+    let segment = segment_with_records(dir.path());
+    let mut bytes = fs::read(&segment).unwrap();
+    let last_value_byte = bytes.len() - 2 * 8 - 1;
+    bytes[last_value_byte] ^= 0x40;
+    bytes.extend_from_slice(&1_000u32.to_le_bytes());
+    bytes.extend_from_slice(&[0xAB; 10]);
+    fs::write(&segment, &bytes).unwrap();
+
+    let restored = open_store_with_wal(dir.path());
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 1);
+
+    let stats = restored.get_stats("AAA", 100).unwrap();
+    assert_eq!(stats.max, 2.0);
+    assert_eq!(restored.symbols.get("AAA").unwrap().len(), 2);
+}
+
+/// Test that the log only holds what the latest snapshot does not
+#[test]
+fn test_wal_with_snapshot_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    let wal_dir = dir.path().join("wal");
+    let snapshot_path = dir.path().join("store.snapshot");
+
+    {
+        let store = open_store_with_wal(&wal_dir).with_snapshot_path(&snapshot_path);
+        store.add_batch("AAA", &[1.0, 2.0, 3.0]).unwrap();
+        store.save_snapshot().unwrap();
+
+        // Segments covered by the snapshot are deleted.
+        assert_eq!(wal_segments(&wal_dir).len(), 1);
+
+        store.add_batch("AAA", &[4.0, 5.0]).unwrap();
+        store.add_batch("BBB", &[100.0]).unwrap();
+    }
+
+    let restored = open_store_with_wal(&wal_dir).with_snapshot_path(&snapshot_path);
+    restored.load_snapshot().unwrap().unwrap();
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 2);
+
+    let stats = restored.get_stats("AAA", 100).unwrap();
+    assert_eq!(restored.symbols.get("AAA").unwrap().len(), 5);
+    assert_eq!(stats.last, 5.0);
+    fuzzy_assert_eq(stats.avg, 3.0);
+    assert_eq!(restored.get_stats("BBB", 1).unwrap().last, 100.0);
+}
+
+/// Test that batches present in both the snapshot and the log are applied once
+#[test]
+fn test_wal_replay_skips_batches_in_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let wal_dir = dir.path().join("wal");
+    let snapshot_path = dir.path().join("store.snapshot");
+
+    {
+        let store = open_store_with_wal(&wal_dir);
+        store.add_batch("AAA", &[1.0, 2.0]).unwrap();
+        store.add_batch("AAA", &[3.0, 4.0]).unwrap();
+        // Write a snapshot without rotating the log, as if the process died
+        // between writing the snapshot and deleting old segments.
+        snapshot::write(&store, &snapshot_path).unwrap();
+        store.add_batch("AAA", &[5.0]).unwrap();
+    }
+
+    let restored = open_store_with_wal(&wal_dir).with_snapshot_path(&snapshot_path);
+    restored.load_snapshot().unwrap().unwrap();
+    restored.replay_wal().unwrap();
+
+    assert_eq!(restored.symbols.get("AAA").unwrap().len(), 5);
+    let stats = restored.get_stats("AAA", 100).unwrap();
+    fuzzy_assert_eq(stats.avg, 3.0);
+    assert_eq!(stats.last, 5.0);
+}
+
+/// Test that a partially written segment header from a crash during rotation is ignored
+#[test]
+fn test_wal_empty_segment_is_ignored() {
+    let dir = tempfile::tempdir().unwrap();
+
+    {
+        let store = open_store_with_wal(dir.path());
+        store.add_batch("AAA", &[1.0]).unwrap();
+    }
+
+    // A segment with only part of its header.
+    let mut file = fs::File::create(dir.path().join("99999999999999999998.wal")).unwrap();
+    file.write_all(b"HFT").unwrap();
+    drop(file);
+
+    let restored = open_store_with_wal(dir.path());
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 1);
+    assert_eq!(restored.get_stats("AAA", 1).unwrap().last, 1.0);
+}
+
+/// Test that deletes and resets are replayed, even over a snapshot taken before them
+#[test]
+fn test_wal_replays_remove_and_reset() {
+    let dir = tempfile::tempdir().unwrap();
+    let wal_dir = dir.path().join("wal");
+    let snapshot_path = dir.path().join("store.snapshot");
+
+    {
+        let store = open_store_with_wal(&wal_dir);
+        store.add_batch("GONE", &[1.0, 2.0]).unwrap();
+        store.add_batch("RESET", &[1.0, 2.0, 3.0]).unwrap();
+        snapshot::write(&store, &snapshot_path).unwrap();
+
+        store.remove_symbol("GONE").unwrap();
+        store.reset_symbol("RESET").unwrap();
+        store.add_batch("RESET", &[9.0]).unwrap();
+    }
+
+    let restored = open_store_with_wal(&wal_dir).with_snapshot_path(&snapshot_path);
+    restored.load_snapshot().unwrap().unwrap();
+    let summary = restored.replay_wal().unwrap().unwrap();
+    assert_eq!(summary.records, 5);
+
+    assert!(restored.symbols.get("GONE").is_none());
+    assert_eq!(restored.symbols.get("RESET").unwrap().len(), 1);
+    let stats = restored.get_stats("RESET", 100).unwrap();
+    assert_eq!((stats.min, stats.max), (9.0, 9.0));
+}
@@ -0,0 +1,403 @@
+use hft_service::{app_router, binary, store::Store, SharedState};
+
+use axum::body::{Body, BodyDataStream};
+use axum::http::{Request, StatusCode};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tower::ServiceExt;
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serves the router on an ephemeral local port, since upgrades to a
+/// streaming connection can't go through `oneshot`.
+async fn spawn_server(state: SharedState) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app_router(state)).await.unwrap();
+    });
+    addr
+}
+
+async fn send_json(client: &mut Client, message: Value) {
+    client
+        .send(Message::Text(message.to_string().into()))
+        .await
+        .unwrap();
+}
+
+/// Waits for the next text frame, failing the test if none arrives in time.
+async fn next_json(client: &mut Client) -> Value {
+    loop {
+        let message = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("Timed out waiting for a frame")
+            .expect("Connection closed")
+            .unwrap();
+        if let Message::Text(text) = message {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+/// Reads the next Server-Sent Event as its name and parsed data, or `None`
+/// once the stream has ended.
+async fn next_event(body: &mut BodyDataStream, buffer: &mut String) -> Option<(String, Value)> {
+    loop {
+        if let Some(end) = buffer.find("\n\n") {
+            let block: String = buffer.drain(..end + 2).collect();
+            let (mut name, mut data) = (String::new(), String::new());
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("event: ") {
+                    name = value.to_string();
+                } else if let Some(value) = line.strip_prefix("data: ") {
+                    data.push_str(value);
+                }
+            }
+            // Blocks without data are keep-alive comments.
+            if !data.is_empty() {
+                return Some((name, serde_json::from_str(&data).unwrap()));
+            }
+            continue;
+        }
+        let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+            .await
+            .expect("Timed out waiting for an event")?
+            .unwrap();
+        buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_ws_stats_pushes_after_every_batch() {
+    let state = SharedState::new(Store::new());
+    let addr = spawn_server(state.clone()).await;
+    let (mut client, _) = connect_async(format!("ws://{}/ws/stats", addr))
+        .await
+        .unwrap();
+
+    // Subscribing pushes the current state straight away, even if it is an error.
+    send_json(
+        &mut client,
+        json!({ "subscribe": [{ "symbol": "ABC", "window": 3 }] }),
+    )
+    .await;
+    let frame = next_json(&mut client).await;
+    assert_eq!(frame["symbol"], "ABC");
+    assert_eq!(frame["window"], 3);
+    assert_eq!(frame["status"], 404);
+
+    // Batches for other symbols are not pushed.
+    state.add_batch("OTHER", &[1.0]).unwrap();
+    state.add_batch("ABC", &[1.0, 2.0]).unwrap();
+    let frame = next_json(&mut client).await;
+    assert_eq!(frame["symbol"], "ABC");
+    assert_eq!(frame["count"], 2);
+    assert_eq!(frame["last"], 2.0);
+
+    state.add_batch("ABC", &[3.0, 4.0]).unwrap();
+    let frame = next_json(&mut client).await;
+    assert_eq!(frame["count"], 3);
+    assert_eq!(frame["min"], 2.0);
+    assert_eq!(frame["sum"], 9.0);
+
+    state.remove_symbol("ABC").unwrap();
+    let frame = next_json(&mut client).await;
+    assert_eq!(frame["status"], 404);
+
+    // After unsubscribing, changes are no longer pushed. Subscribing to
+    // another symbol in the same command confirms that it was applied.
+    send_json(
+        &mut client,
+        json!({ "unsubscribe": ["ABC"], "subscribe": [{ "symbol": "XYZ", "window": 3 }] }),
+    )
+    .await;
+    assert_eq!(next_json(&mut client).await["symbol"], "XYZ");
+    state.add_batch("ABC", &[5.0]).unwrap();
+    let next = tokio::time::timeout(Duration::from_millis(200), client.next()).await;
+    assert!(next.is_err(), "Unexpected frame: {:?}", next);
+}
+
+#[tokio::test]
+async fn test_ws_stats_throttle_coalesces_batches() {
+    let state = SharedState::new(Store::new());
+    state.add_batch("ABC", &[1.0]).unwrap();
+    let addr = spawn_server(state.clone()).await;
+    let (mut client, _) = connect_async(format!("ws://{}/ws/stats", addr))
+        .await
+        .unwrap();
+
+    send_json(
+        &mut client,
+        json!({
+            "subscribe": [{ "symbol": "ABC", "exponent": 1 }, { "symbol": "ABC", "window": 2, "ddof": 1 }],
+            "throttle_ms": 100,
+        }),
+    )
+    .await;
+    assert_eq!(next_json(&mut client).await["window"], 10);
+    assert_eq!(next_json(&mut client).await["status"], 400);
+
+    // Three batches within one interval are pushed as a single frame per window.
+    for value in [2.0, 3.0, 4.0] {
+        state.add_batch("ABC", &[value]).unwrap();
+    }
+    let frame = next_json(&mut client).await;
+    assert_eq!(
+        (frame["window"].clone(), frame["count"].clone()),
+        (json!(10), json!(4))
+    );
+    let frame = next_json(&mut client).await;
+    assert_eq!(
+        (frame["window"].clone(), frame["ddof"].clone()),
+        (json!(2), json!(1))
+    );
+    assert_eq!(frame["var"], 0.5);
+
+    let next = tokio::time::timeout(Duration::from_millis(300), client.next()).await;
+    assert!(next.is_err(), "Unexpected frame: {:?}", next);
+}
+
+#[tokio::test]
+async fn test_ws_stats_rejects_invalid_commands() {
+    let state = SharedState::new(Store::new());
+    let addr = spawn_server(state.clone()).await;
+    let (mut client, _) = connect_async(format!("ws://{}/ws/stats", addr))
+        .await
+        .unwrap();
+
+    for command in [
+        json!({ "subscribe": [{ "symbol": "ABC", "window": 0 }] }),
+        json!({ "subscribe": [{ "symbol": "ABC", "window": 5, "exponent": 1 }] }),
+        json!({ "throttle_ms": 3_600_000 }),
+        json!({ "subscribe": "ABC" }),
+    ] {
+        send_json(&mut client, command).await;
+        let frame = next_json(&mut client).await;
+        assert_eq!(frame["status"], 400, "{}", frame);
+        assert!(frame["error"].is_string());
+    }
+
+    // The connection stays usable after an invalid command, and the rejected
+    // subscriptions were not applied.
+    send_json(
+        &mut client,
+        json!({ "subscribe": [{ "symbol": "ABC", "window": 5 }] }),
+    )
+    .await;
+    assert_eq!(next_json(&mut client).await["window"], 5);
+    state.add_batch("ABC", &[1.0]).unwrap();
+    let frame = next_json(&mut client).await;
+    assert_eq!(
+        (frame["window"].clone(), frame["count"].clone()),
+        (json!(5), json!(1))
+    );
+}
+
+#[tokio::test]
+async fn test_ws_ingest_acknowledges_every_frame() {
+    let state = SharedState::new(Store::new());
+    let addr = spawn_server(state.clone()).await;
+    let (mut client, _) = connect_async(format!("ws://{}/ws/ingest", addr))
+        .await
+        .unwrap();
+
+    send_json(
+        &mut client,
+        json!({ "symbol": "ABC", "values": [1.0, 2.0], "timestamps": [10, 20] }),
+    )
+    .await;
+    send_json(
+        &mut client,
+        json!({ "symbol": "ABC", "values": [3.0, -1.0] }),
+    )
+    .await;
+    send_json(
+        &mut client,
+        json!({ "symbol": "ABC", "values": [3.0, -1.0], "timestamps": [30, 40], "on_invalid": "drop" }),
+    )
+    .await;
+    send_json(&mut client, json!({ "symbol": "ABC" })).await;
+    client
+        .send(Message::Binary(vec![0u8; 8].into()))
+        .await
+        .unwrap();
+    let batch = binary::encode_batch("ABC", &[5.0], Some(&[50]), Default::default());
+    client.send(Message::Binary(batch.into())).await.unwrap();
+
+    assert_eq!(
+        next_json(&mut client).await,
+        json!({ "seq": 0, "symbol": "ABC", "status": 200 })
+    );
+    let ack = next_json(&mut client).await;
+    assert_eq!(
+        (ack["seq"].clone(), ack["status"].clone()),
+        (json!(1), json!(400))
+    );
+    assert!(ack["error"].as_str().unwrap().contains("indices [1]"));
+    assert_eq!(
+        next_json(&mut client).await,
+        json!({ "seq": 2, "symbol": "ABC", "status": 200, "dropped": [1] })
+    );
+    let ack = next_json(&mut client).await;
+    assert_eq!(
+        (ack["seq"].clone(), ack["status"].clone()),
+        (json!(3), json!(400))
+    );
+    assert!(ack.get("symbol").is_none());
+    let ack = next_json(&mut client).await;
+    assert_eq!(
+        (ack["seq"].clone(), ack["status"].clone()),
+        (json!(4), json!(400))
+    );
+    assert!(ack["error"].as_str().unwrap().contains("binary"));
+    assert_eq!(
+        next_json(&mut client).await,
+        json!({ "seq": 5, "symbol": "ABC", "status": 200 })
+    );
+
+    let stats = state.get_stats("ABC", 10).unwrap();
+    assert_eq!((stats.count, stats.sum), (4, 11.0));
+}
+
+/// Test that frames pipelined faster than they are acknowledged are all
+/// applied in order, each acknowledged exactly once.
+#[tokio::test]
+async fn test_ws_ingest_pipelined_frames() {
+    let state = SharedState::new(Store::new());
+    let addr = spawn_server(state.clone()).await;
+    let (client, _) = connect_async(format!("ws://{}/ws/ingest", addr))
+        .await
+        .unwrap();
+    let (mut sink, mut stream) = client.split();
+    let frames = 500;
+
+    // Send from one task while reading acks from another, as a feed handler would.
+    let sender = tokio::spawn(async move {
+        for i in 0..frames {
+            let frame = json!({ "symbol": "ABC", "values": [i as f64], "timestamps": [i] });
+            sink.send(Message::Text(frame.to_string().into()))
+                .await
+                .unwrap();
+        }
+    });
+    for i in 0..frames {
+        let message = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let ack: Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(ack, json!({ "seq": i, "symbol": "ABC", "status": 200 }));
+    }
+    sender.await.unwrap();
+
+    let stats = state.get_stats("ABC", frames as usize).unwrap();
+    assert_eq!(stats.count, frames);
+    assert_eq!(stats.last, (frames - 1) as f64);
+}
+
+/// Test that closing the store's streams ends open push connections, as a
+/// graceful shutdown does.
+#[tokio::test]
+async fn test_ws_streams_close_on_shutdown() {
+    let state = SharedState::new(Store::new());
+    let addr = spawn_server(state.clone()).await;
+    let (mut stats, _) = connect_async(format!("ws://{}/ws/stats", addr))
+        .await
+        .unwrap();
+    let (mut ingest, _) = connect_async(format!("ws://{}/ws/ingest", addr))
+        .await
+        .unwrap();
+    send_json(&mut ingest, json!({ "symbol": "ABC", "values": [1.0] })).await;
+    assert_eq!(next_json(&mut ingest).await["status"], 200);
+
+    state.close_streams();
+
+    for client in [&mut stats, &mut ingest] {
+        let message = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("Connection was not closed");
+        assert!(
+            matches!(message, Some(Ok(Message::Close(_))) | None),
+            "Unexpected message: {:?}",
+            message
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_events_stream_stats_of_changed_symbols() {
+    let state = SharedState::new(Store::new());
+    state.add_batch("ABC", &[1.0, 2.0]).unwrap();
+    let app = app_router(state.clone());
+
+    let request = Request::builder()
+        .uri("/events?symbols=ABC,XYZ,ABC&window=3&ddof=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let mut body = response.into_body().into_data_stream();
+    let mut buffer = String::new();
+
+    // Every symbol is sent once straight away, including those without data.
+    let (name, data) = next_event(&mut body, &mut buffer).await.unwrap();
+    assert_eq!(name, "stats");
+    assert_eq!(
+        (data["symbol"].clone(), data["count"].clone()),
+        (json!("ABC"), json!(2))
+    );
+    assert_eq!(data["var"], 0.5);
+    let (_, data) = next_event(&mut body, &mut buffer).await.unwrap();
+    assert_eq!(
+        (data["symbol"].clone(), data["status"].clone()),
+        (json!("XYZ"), json!(404))
+    );
+
+    // Only the symbols that change are sent again.
+    state.add_batch("OTHER", &[1.0]).unwrap();
+    state.add_batch("XYZ", &[5.0, 7.0]).unwrap();
+    let (_, data) = next_event(&mut body, &mut buffer).await.unwrap();
+    assert_eq!(
+        (data["symbol"].clone(), data["window"].clone()),
+        (json!("XYZ"), json!(3))
+    );
+    assert_eq!(
+        (data["avg"].clone(), data["ddof"].clone()),
+        (json!(6.0), json!(1))
+    );
+
+    // The stream ends cleanly on shutdown.
+    state.close_streams();
+    assert!(next_event(&mut body, &mut buffer).await.is_none());
+}
+
+#[tokio::test]
+async fn test_events_rejects_invalid_parameters() {
+    let state = SharedState::new(Store::new());
+    let app = app_router(state);
+
+    let too_many = (0..101)
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    for query in [
+        "symbols=ABC".to_string(),
+        "symbols=ABC,,XYZ&window=10".to_string(),
+        "symbols=ABC&window=10&ddof=2".to_string(),
+        format!("symbols={}&window=10", too_many),
+    ] {
+        let request = Request::builder()
+            .uri(format!("/events?{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}